@@ -0,0 +1,26 @@
+//! Timing helpers shared by the benchmarks.
+//!
+//! These use a simple timing loop rather than a benchmarking framework,
+//! to avoid adding dependencies.
+
+use std::time::{Duration, Instant};
+
+/// Runs `f` repeatedly for at least `min_time`, and prints the average
+/// time per run.
+pub fn bench<F: FnMut()>(name: &str, min_time: Duration, mut f: F) {
+    // Warm up.
+    f();
+
+    let start = Instant::now();
+    let mut runs = 0u32;
+    while start.elapsed() < min_time {
+        f();
+        runs += 1;
+    }
+    let per_run = start.elapsed() / runs;
+    println!(
+        "{:<40} {:>10.2} ms/iter",
+        name,
+        per_run.as_secs_f64() * 1000.0
+    );
+}
@@ -0,0 +1,103 @@
+//! Throughput benchmarks for the text LUT formats.
+//!
+//! Run with `cargo bench --bench formats`.
+
+mod common;
+
+use std::hint::black_box;
+use std::time::Duration;
+
+use colorbox::formats::{cube_iridas, cube_resolve, spi1d, CubeMetadata, WriteOptions};
+use colorbox::lut::{Lut1D, Lut3D};
+use common::bench;
+
+fn main() {
+    let min_time = Duration::from_secs(2);
+    let metadata = CubeMetadata::default();
+    let options = WriteOptions::default();
+    let file_path = std::env::temp_dir().join("colorbox_bench.cube");
+
+    for res in [65, 129] {
+        let lut = Lut3D::from_fn([res; 3], [0.0; 3], [1.0; 3], |(r, g, b)| {
+            (r.powf(2.2), g * 0.5 + b * 0.5, (r + g + b) / 3.0)
+        });
+        let tables = [&lut.tables[0][..], &lut.tables[1][..], &lut.tables[2][..]];
+
+        let mut iridas_data = Vec::new();
+        cube_iridas::write_3d(
+            &mut iridas_data,
+            &metadata,
+            lut.range,
+            res,
+            tables,
+            &options,
+        )
+        .unwrap();
+        let mut resolve_data = Vec::new();
+        cube_resolve::write(
+            &mut resolve_data,
+            &metadata,
+            None,
+            Some((0.0, 1.0, res, tables)),
+            &options,
+        )
+        .unwrap();
+
+        bench(
+            &format!("cube_iridas::write_3d {}^3", res),
+            min_time,
+            || {
+                let mut data = Vec::with_capacity(iridas_data.len());
+                cube_iridas::write_3d(&mut data, &metadata, lut.range, res, tables, &options)
+                    .unwrap();
+                black_box(data);
+            },
+        );
+        bench(
+            &format!("cube_iridas::write_3d {}^3 (file)", res),
+            min_time,
+            || {
+                let file = std::fs::File::create(&file_path).unwrap();
+                cube_iridas::write_3d(file, &metadata, lut.range, res, tables, &options).unwrap();
+            },
+        );
+        bench(&format!("cube_iridas::read_3d {}^3", res), min_time, || {
+            black_box(cube_iridas::read_3d(&iridas_data[..]).unwrap());
+        });
+        bench(&format!("cube_resolve::write {}^3", res), min_time, || {
+            let mut data = Vec::with_capacity(resolve_data.len());
+            cube_resolve::write(
+                &mut data,
+                &metadata,
+                None,
+                Some((0.0, 1.0, res, tables)),
+                &options,
+            )
+            .unwrap();
+            black_box(data);
+        });
+        bench(&format!("cube_resolve::read {}^3", res), min_time, || {
+            black_box(cube_resolve::read(&resolve_data[..]).unwrap());
+        });
+    }
+
+    let lut = Lut1D::from_fn_1(1 << 16, 0.0, 1.0, |n| n.powf(2.2));
+    let tables = [&lut.tables[0][..]];
+    let mut spi1d_data = Vec::new();
+    spi1d::write(&mut spi1d_data, 0.0, 1.0, &tables, &options).unwrap();
+
+    bench("spi1d::write 65536", min_time, || {
+        let mut data = Vec::with_capacity(spi1d_data.len());
+        spi1d::write(&mut data, 0.0, 1.0, &tables, &options).unwrap();
+        black_box(data);
+    });
+    bench("spi1d::write 65536 (file)", min_time, || {
+        let file = std::fs::File::create(&file_path).unwrap();
+        spi1d::write(file, 0.0, 1.0, &tables, &options).unwrap();
+    });
+    bench("spi1d::read 65536", min_time, || {
+        black_box(spi1d::read(&spi1d_data[..]).unwrap());
+    });
+
+    std::fs::remove_file(&file_path).ok();
+}
@@ -0,0 +1,45 @@
+//! Throughput benchmarks for applying LUTs to image buffers.
+//!
+//! Run with `cargo bench --bench lut`.
+
+mod common;
+
+use std::hint::black_box;
+use std::time::Duration;
+
+use colorbox::lut::{Interpolation3D, Lut1D, Lut3D};
+use common::bench;
+
+fn main() {
+    let min_time = Duration::from_secs(2);
+
+    // A 1080p RGBA frame.
+    let frame: Vec<f32> = (0..(1920 * 1080 * 4))
+        .map(|i| ((i as f32) * 0.000_123).fract())
+        .collect();
+
+    let lut_1d = Lut1D::from_fn_1(4096, 0.0, 1.0, |n| n.powf(1.0 / 2.2));
+    bench("Lut1D::apply_interleaved 1080p", min_time, || {
+        let mut data = frame.clone();
+        lut_1d.apply_interleaved(&mut data, 4);
+        black_box(data);
+    });
+
+    let lut_3d = Lut3D::from_fn([33; 3], [0.0; 3], [1.0; 3], |(r, g, b)| {
+        (r.powf(2.2), g * 0.5 + b * 0.5, (r + g + b) / 3.0)
+    });
+    for interpolation in [Interpolation3D::Trilinear, Interpolation3D::Tetrahedral] {
+        bench(
+            &format!("Lut3D::apply_interleaved 1080p {:?}", interpolation),
+            min_time,
+            || {
+                let mut data = frame.clone();
+                lut_3d.apply_interleaved(&mut data, 4, interpolation);
+                black_box(data);
+            },
+        );
+    }
+    bench("Lut3D::invert 33^3", min_time, || {
+        black_box(lut_3d.invert([33; 3], [(0.0, 1.0); 3], 0.0001).unwrap());
+    });
+}
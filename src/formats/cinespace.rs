@@ -0,0 +1,377 @@
+//! Cinespace .csp LUT format.
+//!
+//! This format contains a 3D LUT preceded by a per-channel 1D "pre-LUT"
+//! (or shaper).  The pre-LUTs are specified as non-uniform breakpoints,
+//! and map the input values into the `[0.0, 1.0]` domain of the 3D LUT.
+//!
+//! Only the 3D variant of the format is supported.
+
+use std::io::{BufRead, Write};
+
+use super::{filter_non_finite, parse, parse_finite, FormatErrorKind, ReadError};
+use crate::lut::{validate_3d, Lut1DNonUniform, Lut3D, LutError};
+
+/// Writes a .csp file.
+///
+/// - `shapers`: the per-channel pre-LUT breakpoints, as `(input, output)`
+///   pairs.  The inputs must be finite and strictly increasing.
+/// - `resolution`: the resolution of the 3D LUT.
+/// - `tables`: the 3D LUT tables.  The tables should have indices ordered
+///   the same as the `Lut3D` type.
+///
+/// Invalid LUT data is reported as an error of kind `InvalidInput`.
+pub fn write<W: Write>(
+    mut writer: W,
+    shapers: [&[(f32, f32)]; 3],
+    resolution: [usize; 3],
+    tables: [&[f32]; 3],
+) -> std::io::Result<()> {
+    for (i, shaper) in shapers.iter().enumerate() {
+        if shaper.len() < 2 {
+            return Err(LutError::TableTooShort {
+                index: i,
+                len: shaper.len(),
+            }
+            .into());
+        }
+        let increasing = shaper
+            .windows(2)
+            .all(|pair| pair[0].0.is_finite() && pair[1].0.is_finite() && pair[0].0 < pair[1].0);
+        if !increasing {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "shaper inputs must be finite and strictly increasing",
+            ));
+        }
+    }
+    validate_3d(&[(0.0, 1.0); 3], resolution, &tables)?;
+
+    writer.write_all(b"CSPLUTV100\n")?;
+    writer.write_all(b"3D\n\n")?;
+
+    // Pre-LUTs.
+    for shaper in shapers.iter() {
+        writer.write_all(format!("{}\n", shaper.len()).as_bytes())?;
+        for (i, (x, _)) in shaper.iter().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            writer.write_all(format!("{}{}", sep, filter_non_finite(*x)).as_bytes())?;
+        }
+        writer.write_all(b"\n")?;
+        for (i, (_, y)) in shaper.iter().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            writer.write_all(format!("{}{}", sep, filter_non_finite(*y)).as_bytes())?;
+        }
+        writer.write_all(b"\n")?;
+    }
+    writer.write_all(b"\n")?;
+
+    // 3D LUT.
+    writer
+        .write_all(format!("{} {} {}\n", resolution[0], resolution[1], resolution[2]).as_bytes())?;
+    for ((r, g), b) in tables[0]
+        .iter()
+        .copied()
+        .zip(tables[1].iter().copied())
+        .zip(tables[2].iter().copied())
+    {
+        writer.write_all(
+            format!(
+                "{} {} {}\n",
+                filter_non_finite(r),
+                filter_non_finite(g),
+                filter_non_finite(b),
+            )
+            .as_bytes(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Reads a .csp file.
+///
+/// Returns the pre-LUTs, as a three-table non-uniform 1D LUT, and the
+/// 3D LUT.  The 3D LUT always has an input range of `[0.0, 1.0]`.
+pub fn read<R: BufRead>(reader: R) -> Result<(Lut1DNonUniform, Lut3D), ReadError> {
+    let mut lines = reader.lines().enumerate();
+    let mut line_n = 0;
+
+    // Fetches the next non-blank line, split into parts.  Reaching the
+    // end of the file is an error.
+    let mut next_line = |line_n: &mut usize| -> Result<Vec<String>, ReadError> {
+        for (i, line) in lines.by_ref() {
+            let line = line?;
+            *line_n = i + 1;
+            let parts: Vec<String> = line.split_whitespace().map(|s| s.into()).collect();
+            if !parts.is_empty() {
+                return Ok(parts);
+            }
+        }
+        Err(ReadError::format(
+            *line_n,
+            "",
+            FormatErrorKind::UnexpectedEnd,
+        ))
+    };
+    let bad_keyword = |line_n: usize, parts: &[String]| {
+        ReadError::format(line_n, &parts[0], FormatErrorKind::BadKeyword)
+    };
+    let wrong_count = |line_n: usize, parts: &[String], expected: usize| {
+        ReadError::format(
+            line_n,
+            &parts.join(" "),
+            FormatErrorKind::WrongValueCount {
+                expected,
+                found: parts.len(),
+            },
+        )
+    };
+
+    // Header.
+    let parts = next_line(&mut line_n)?;
+    if parts != ["CSPLUTV100"] {
+        return Err(bad_keyword(line_n, &parts));
+    }
+    let parts = next_line(&mut line_n)?;
+    if parts != ["3D"] {
+        return Err(bad_keyword(line_n, &parts));
+    }
+
+    // Skip metadata, if any.
+    let mut parts = next_line(&mut line_n)?;
+    if parts == ["BEGIN", "METADATA"] {
+        while next_line(&mut line_n)? != ["END", "METADATA"] {}
+        parts = next_line(&mut line_n)?;
+    }
+
+    // Pre-LUTs.
+    let mut shapers = [Vec::new(), Vec::new(), Vec::new()];
+    for shaper in shapers.iter_mut() {
+        if parts.len() != 1 {
+            return Err(wrong_count(line_n, &parts, 1));
+        }
+        let len = parse::<usize>(&parts[0], line_n)?;
+        if len < 2 {
+            return Err(ReadError::format(
+                line_n,
+                &parts[0],
+                FormatErrorKind::InvalidValue,
+            ));
+        }
+
+        let inputs = next_line(&mut line_n)?;
+        if inputs.len() != len {
+            return Err(wrong_count(line_n, &inputs, len));
+        }
+        for token in inputs.iter() {
+            let x = parse_finite::<f32>(token, line_n)?;
+            if shaper.last().map(|(last, _)| x <= *last).unwrap_or(false) {
+                // Inputs must be strictly increasing.
+                return Err(ReadError::format(
+                    line_n,
+                    token,
+                    FormatErrorKind::InvalidValue,
+                ));
+            }
+            shaper.push((x, 0.0));
+        }
+
+        let outputs = next_line(&mut line_n)?;
+        if outputs.len() != len {
+            return Err(wrong_count(line_n, &outputs, len));
+        }
+        for (point, y) in shaper.iter_mut().zip(outputs.iter()) {
+            point.1 = parse_finite::<f32>(y, line_n)?;
+        }
+
+        parts = next_line(&mut line_n)?;
+    }
+
+    // 3D LUT.
+    if parts.len() != 3 {
+        return Err(wrong_count(line_n, &parts, 3));
+    }
+    let resolution_line = line_n;
+    let mut resolution = [0usize; 3];
+    for (r, part) in resolution.iter_mut().zip(parts.iter()) {
+        *r = parse::<usize>(part, line_n)?;
+        if *r < 2 {
+            return Err(ReadError::format(
+                line_n,
+                part,
+                FormatErrorKind::InvalidValue,
+            ));
+        }
+    }
+    let len = match resolution[0]
+        .checked_mul(resolution[1])
+        .and_then(|n| n.checked_mul(resolution[2]))
+    {
+        Some(len) => len,
+        None => {
+            return Err(ReadError::format(
+                line_n,
+                &parts.join(" "),
+                FormatErrorKind::InvalidValue,
+            ))
+        }
+    };
+    let mut tables = [Vec::new(), Vec::new(), Vec::new()];
+    while tables[0].len() < len {
+        let parts = match next_line(&mut line_n) {
+            Ok(parts) => parts,
+            Err(ReadError::FormatErr { .. }) => {
+                return Err(ReadError::format(
+                    resolution_line,
+                    "",
+                    FormatErrorKind::SizeMismatch {
+                        expected: len,
+                        found: tables[0].len(),
+                    },
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        if parts.len() != 3 {
+            return Err(wrong_count(line_n, &parts, 3));
+        }
+        tables[0].push(parse_finite(&parts[0], line_n)?);
+        tables[1].push(parse_finite(&parts[1], line_n)?);
+        tables[2].push(parse_finite(&parts[2], line_n)?);
+    }
+
+    // Ensure there's no trailing data.
+    match next_line(&mut line_n) {
+        Ok(parts) => {
+            return Err(ReadError::format(
+                line_n,
+                &parts.join(" "),
+                FormatErrorKind::SizeMismatch {
+                    expected: len,
+                    found: len + 1,
+                },
+            ))
+        }
+        // End of file.
+        Err(ReadError::FormatErr { .. }) => {}
+        Err(e) => return Err(e),
+    }
+
+    let [table_r, table_g, table_b] = tables;
+    let [shaper_r, shaper_g, shaper_b] = shapers;
+    let lut_3d = Lut3D {
+        range: [(0.0, 1.0); 3],
+        resolution,
+        tables: vec![table_r, table_g, table_b],
+    };
+    lut_3d
+        .validate()
+        .map_err(|e| ReadError::invalid_lut(resolution_line, "", e))?;
+
+    Ok((
+        Lut1DNonUniform {
+            tables: vec![shaper_r, shaper_g, shaper_b],
+        },
+        lut_3d,
+    ))
+}
+
+//-------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_CSP: &str = "CSPLUTV100
+3D
+
+BEGIN METADATA
+Some metadata.
+END METADATA
+
+2
+0.0 4.0
+0.0 1.0
+3
+0.0 0.5 1.0
+0.0 0.75 1.0
+2
+-1.0 1.0
+0.0 1.0
+
+2 2 2
+0.0 0.0 0.0
+1.0 0.0 0.0
+0.0 1.0 0.0
+1.0 1.0 0.0
+0.0 0.0 1.0
+1.0 0.0 1.0
+0.0 1.0 1.0
+1.0 1.0 1.0
+";
+
+    #[test]
+    fn read_01() {
+        let (shapers, lut) = read(TEST_CSP.as_bytes()).unwrap();
+
+        assert_eq!(shapers.tables[0], vec![(0.0, 0.0), (4.0, 1.0)]);
+        assert_eq!(shapers.tables[1], vec![(0.0, 0.0), (0.5, 0.75), (1.0, 1.0)]);
+        assert_eq!(shapers.tables[2], vec![(-1.0, 0.0), (1.0, 1.0)]);
+        assert_eq!(lut.resolution, [2, 2, 2]);
+        assert_eq!(lut.tables[0], vec![0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let (shapers, lut) = read(TEST_CSP.as_bytes()).unwrap();
+
+        let mut data = Vec::new();
+        write(
+            &mut data,
+            [&shapers.tables[0], &shapers.tables[1], &shapers.tables[2]],
+            lut.resolution,
+            [&lut.tables[0], &lut.tables[1], &lut.tables[2]],
+        )
+        .unwrap();
+        let (shapers2, lut2) = read(&data[..]).unwrap();
+
+        assert_eq!(shapers, shapers2);
+        assert_eq!(lut.resolution, lut2.resolution);
+        assert_eq!(lut.tables, lut2.tables);
+    }
+
+    #[test]
+    fn read_errors() {
+        // Huge resolution.
+        let data = TEST_CSP.replace("2 2 2\n", "4294967296 4294967296 2\n");
+        assert!(matches!(
+            read(data.as_bytes()),
+            Err(ReadError::FormatErr {
+                line: 18,
+                kind: FormatErrorKind::InvalidValue,
+                ..
+            })
+        ));
+
+        // IO errors (here, invalid UTF-8) after the data aren't ignored.
+        let mut data = TEST_CSP.as_bytes().to_vec();
+        data.extend_from_slice(b"\xff\xfe\n");
+        assert!(matches!(read(&data[..]), Err(ReadError::IoErr(_))));
+    }
+
+    #[test]
+    fn write_bad_shaper() {
+        let (shapers, lut) = read(TEST_CSP.as_bytes()).unwrap();
+
+        let bad_shaper = [(0.0, 0.0), (0.5, 0.5), (0.5, 1.0)];
+        let mut data = Vec::new();
+        let result = write(
+            &mut data,
+            [&shapers.tables[0], &bad_shaper, &shapers.tables[2]],
+            lut.resolution,
+            [&lut.tables[0], &lut.tables[1], &lut.tables[2]],
+        );
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert!(data.is_empty());
+    }
+}
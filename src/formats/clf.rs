@@ -0,0 +1,1380 @@
+//! Academy/ASC Common LUT Format (.clf).
+//!
+//! CLF files are XML documents containing a `ProcessList`: a sequence
+//! of process nodes (matrices, LUTs, log curves, etc.) that are applied
+//! in order.
+//!
+//! All values are normalized to floating point when reading, regardless
+//! of the bit depths specified in the file.  For example, a `LUT1D` with
+//! an `outBitDepth` of `10i` will have its table values divided by 1023.
+//! Correspondingly, everything is written with bit depths of `32f`.
+
+// Implemented according to:
+// https://docs.acescentral.com/specifications/clf/
+
+use std::io::{BufRead, Write};
+
+use super::{filter_non_finite, parse, parse_finite, FormatErrorKind, ReadError};
+use crate::lut::{
+    f16_bits_to_f32, validate_table_lengths, Lut1D, Lut1DHalfDomain, Lut3D, LutError,
+};
+use crate::matrix::Matrix;
+
+/// A CLF process list.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessList {
+    pub id: String,
+    pub name: Option<String>,
+    pub description: Vec<String>,
+    pub nodes: Vec<ProcessNode>,
+}
+
+/// A single process node in a `ProcessList`.
+#[derive(Debug, Clone)]
+pub enum ProcessNode {
+    /// A 3x3 matrix, with offsets that are added after the matrix is
+    /// applied.
+    Matrix { matrix: Matrix, offset: [f64; 3] },
+
+    /// A 1D LUT.  Its input range is always `[0.0, 1.0]` when read
+    /// from a file.
+    Lut1D(Lut1D),
+
+    /// A 1D LUT with the `halfDomain` attribute, indexed by the bits of
+    /// f16 inputs.  Must have either one table or three.
+    Lut1DHalfDomain(Lut1DHalfDomain),
+
+    /// A 3D LUT.  Its input range is always `[0.0, 1.0]` when read
+    /// from a file.
+    Lut3D(Lut3D),
+
+    /// Linearly maps `[min_in, max_in]` to `[min_out, max_out]`.
+    ///
+    /// If only the minimum or maximum values are given, the node
+    /// offsets by the difference of the given values and clamps on
+    /// that side only.  If `clamp` is false, no clamping is done.
+    Range {
+        min_in: Option<f64>,
+        max_in: Option<f64>,
+        min_out: Option<f64>,
+        max_out: Option<f64>,
+        clamp: bool,
+    },
+
+    /// A logarithmic or anti-logarithmic curve, with per-channel
+    /// parameters.
+    Log {
+        style: LogStyle,
+        params: [LogParams; 3],
+    },
+
+    /// An ASC Color Decision List.
+    AscCdl {
+        style: CdlStyle,
+        slope: [f64; 3],
+        offset: [f64; 3],
+        power: [f64; 3],
+        saturation: f64,
+    },
+}
+
+/// The style of a `Log` process node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogStyle {
+    Log10,
+    AntiLog10,
+    Log2,
+    AntiLog2,
+    LinToLog,
+    LogToLin,
+    CameraLinToLog,
+    CameraLogToLin,
+}
+
+/// The parameters of a `Log` process node, for a single channel.
+///
+/// The `lin_side_break` and `linear_slope` parameters are only used
+/// by the camera log styles.  When `linear_slope` is `None` for those
+/// styles, it is computed to make the curve continuous.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LogParams {
+    pub base: f64,
+    pub log_side_slope: f64,
+    pub log_side_offset: f64,
+    pub lin_side_slope: f64,
+    pub lin_side_offset: f64,
+    pub lin_side_break: Option<f64>,
+    pub linear_slope: Option<f64>,
+}
+
+impl Default for LogParams {
+    fn default() -> LogParams {
+        LogParams {
+            base: 2.0,
+            log_side_slope: 1.0,
+            log_side_offset: 0.0,
+            lin_side_slope: 1.0,
+            lin_side_offset: 0.0,
+            lin_side_break: None,
+            linear_slope: None,
+        }
+    }
+}
+
+/// The style of an `AscCdl` process node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CdlStyle {
+    /// v1.2 forward, with clamping.
+    Fwd,
+    /// v1.2 inverse, with clamping.
+    Rev,
+    /// Forward without clamping.
+    FwdNoClamp,
+    /// Inverse without clamping.
+    RevNoClamp,
+}
+
+//-------------------------------------------------------------
+// Writing.
+
+/// Writes a CLF file.
+///
+/// All process nodes are written with bit depths of `32f`.
+///
+/// Since CLF LUTs always have an input domain of `[0.0, 1.0]`, LUTs
+/// with a different input range are preceded by a `Matrix` node that
+/// maps their range to `[0.0, 1.0]`.
+///
+/// NaN and infinite LUT values are written as zero.  Invalid LUT nodes,
+/// and NaN or infinite parameters of other nodes, are reported as an
+/// error of kind `InvalidInput`, before anything is written.
+pub fn write<W: Write>(mut writer: W, process_list: &ProcessList) -> std::io::Result<()> {
+    for node in process_list.nodes.iter() {
+        validate_node(node)?;
+    }
+
+    writer.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n")?;
+    writer.write_all(
+        format!(
+            "<ProcessList compCLFversion=\"3.0\" id=\"{}\"",
+            escape(&process_list.id)
+        )
+        .as_bytes(),
+    )?;
+    if let Some(name) = &process_list.name {
+        writer.write_all(format!(" name=\"{}\"", escape(name)).as_bytes())?;
+    }
+    writer.write_all(b">\n")?;
+    for desc in process_list.description.iter() {
+        writer
+            .write_all(format!("    <Description>{}</Description>\n", escape(desc)).as_bytes())?;
+    }
+
+    for node in process_list.nodes.iter() {
+        match node {
+            ProcessNode::Matrix { matrix, offset } => {
+                write_matrix(&mut writer, matrix, offset)?;
+            }
+
+            ProcessNode::Lut1D(lut) => {
+                let ranges = if lut.ranges.len() == 1 {
+                    [lut.ranges[0]; 3]
+                } else {
+                    [lut.ranges[0], lut.ranges[1], lut.ranges[2]]
+                };
+                write_range_normalizer(&mut writer, ranges)?;
+
+                writer.write_all(
+                    b"    <LUT1D inBitDepth=\"32f\" outBitDepth=\"32f\" interpolation=\"linear\">\n",
+                )?;
+                writer.write_all(
+                    format!(
+                        "        <Array dim=\"{} {}\">\n",
+                        lut.tables[0].len(),
+                        lut.tables.len()
+                    )
+                    .as_bytes(),
+                )?;
+                for i in 0..lut.tables[0].len() {
+                    writer.write_all(b"           ")?;
+                    for table in lut.tables.iter() {
+                        writer.write_all(format!(" {}", filter_non_finite(table[i])).as_bytes())?;
+                    }
+                    writer.write_all(b"\n")?;
+                }
+                writer.write_all(b"        </Array>\n")?;
+                writer.write_all(b"    </LUT1D>\n")?;
+            }
+
+            ProcessNode::Lut1DHalfDomain(lut) => {
+                writer.write_all(
+                    b"    <LUT1D inBitDepth=\"16f\" outBitDepth=\"32f\" halfDomain=\"true\">\n",
+                )?;
+                writer.write_all(
+                    format!(
+                        "        <Array dim=\"{} {}\">\n",
+                        Lut1DHalfDomain::SAMPLES,
+                        lut.tables.len()
+                    )
+                    .as_bytes(),
+                )?;
+                for i in 0..Lut1DHalfDomain::SAMPLES {
+                    writer.write_all(b"           ")?;
+                    for table in lut.tables.iter() {
+                        writer.write_all(format!(" {}", filter_non_finite(table[i])).as_bytes())?;
+                    }
+                    writer.write_all(b"\n")?;
+                }
+                writer.write_all(b"        </Array>\n")?;
+                writer.write_all(b"    </LUT1D>\n")?;
+            }
+
+            ProcessNode::Lut3D(lut) => {
+                let res = lut.resolution[0];
+
+                write_range_normalizer(&mut writer, lut.range)?;
+
+                writer.write_all(
+                    b"    <LUT3D inBitDepth=\"32f\" outBitDepth=\"32f\" interpolation=\"trilinear\">\n",
+                )?;
+                writer.write_all(
+                    format!("        <Array dim=\"{} {} {} 3\">\n", res, res, res).as_bytes(),
+                )?;
+                // CLF orders its 3D LUT data with blue changing fastest,
+                // which is the opposite of `Lut3D`.
+                for ri in 0..res {
+                    for gi in 0..res {
+                        for bi in 0..res {
+                            let i = ri + (gi * res) + (bi * res * res);
+                            writer.write_all(
+                                format!(
+                                    "            {} {} {}\n",
+                                    filter_non_finite(lut.tables[0][i]),
+                                    filter_non_finite(lut.tables[1][i]),
+                                    filter_non_finite(lut.tables[2][i]),
+                                )
+                                .as_bytes(),
+                            )?;
+                        }
+                    }
+                }
+                writer.write_all(b"        </Array>\n")?;
+                writer.write_all(b"    </LUT3D>\n")?;
+            }
+
+            ProcessNode::Range {
+                min_in,
+                max_in,
+                min_out,
+                max_out,
+                clamp,
+            } => {
+                writer.write_all(b"    <Range inBitDepth=\"32f\" outBitDepth=\"32f\"")?;
+                if !clamp {
+                    writer.write_all(b" style=\"noClamp\"")?;
+                }
+                writer.write_all(b">\n")?;
+                for (tag, value) in [
+                    ("minInValue", min_in),
+                    ("maxInValue", max_in),
+                    ("minOutValue", min_out),
+                    ("maxOutValue", max_out),
+                ] {
+                    if let Some(v) = value {
+                        writer.write_all(format!("        <{0}>{1}</{0}>\n", tag, v).as_bytes())?;
+                    }
+                }
+                writer.write_all(b"    </Range>\n")?;
+            }
+
+            ProcessNode::Log { style, params } => {
+                writer.write_all(
+                    format!(
+                        "    <Log inBitDepth=\"32f\" outBitDepth=\"32f\" style=\"{}\">\n",
+                        log_style_name(*style)
+                    )
+                    .as_bytes(),
+                )?;
+                let is_camera =
+                    matches!(style, LogStyle::CameraLinToLog | LogStyle::CameraLogToLin);
+                let needs_params =
+                    is_camera || matches!(style, LogStyle::LinToLog | LogStyle::LogToLin);
+                if needs_params {
+                    for (p, channel) in params.iter().zip(["R", "G", "B"]) {
+                        writer.write_all(
+                            format!(
+                                "        <LogParams channel=\"{}\" base=\"{}\" logSideSlope=\"{}\" logSideOffset=\"{}\" linSideSlope=\"{}\" linSideOffset=\"{}\"",
+                                channel,
+                                p.base,
+                                p.log_side_slope,
+                                p.log_side_offset,
+                                p.lin_side_slope,
+                                p.lin_side_offset,
+                            )
+                            .as_bytes(),
+                        )?;
+                        if is_camera {
+                            if let Some(lin_side_break) = p.lin_side_break {
+                                writer.write_all(
+                                    format!(" linSideBreak=\"{}\"", lin_side_break).as_bytes(),
+                                )?;
+                            }
+                            if let Some(linear_slope) = p.linear_slope {
+                                writer.write_all(
+                                    format!(" linearSlope=\"{}\"", linear_slope).as_bytes(),
+                                )?;
+                            }
+                        }
+                        writer.write_all(b" />\n")?;
+                    }
+                }
+                writer.write_all(b"    </Log>\n")?;
+            }
+
+            ProcessNode::AscCdl {
+                style,
+                slope,
+                offset,
+                power,
+                saturation,
+            } => {
+                writer.write_all(
+                    format!(
+                        "    <ASC_CDL inBitDepth=\"32f\" outBitDepth=\"32f\" style=\"{}\">\n",
+                        cdl_style_name(*style)
+                    )
+                    .as_bytes(),
+                )?;
+                writer.write_all(b"        <SOPNode>\n")?;
+                for (tag, v) in [("Slope", slope), ("Offset", offset), ("Power", power)] {
+                    writer.write_all(
+                        format!(
+                            "            <{0}>{1} {2} {3}</{0}>\n",
+                            tag, v[0], v[1], v[2]
+                        )
+                        .as_bytes(),
+                    )?;
+                }
+                writer.write_all(b"        </SOPNode>\n")?;
+                writer.write_all(b"        <SatNode>\n")?;
+                writer.write_all(
+                    format!("            <Saturation>{}</Saturation>\n", saturation).as_bytes(),
+                )?;
+                writer.write_all(b"        </SatNode>\n")?;
+                writer.write_all(b"    </ASC_CDL>\n")?;
+            }
+        }
+    }
+
+    writer.write_all(b"</ProcessList>\n")?;
+
+    Ok(())
+}
+
+/// Checks that a node can be written.
+fn validate_node(node: &ProcessNode) -> std::io::Result<()> {
+    // 1D LUTs must have either one table or three.
+    let check_table_count = |count: usize| {
+        if count == 1 || count == 3 {
+            Ok(())
+        } else {
+            Err(LutError::WrongTableCount {
+                expected: 3,
+                found: count,
+            })
+        }
+    };
+
+    // Parameters must be finite, since they can't be read back otherwise.
+    let check_finite = |values: &[f64]| {
+        if values.iter().all(|n| n.is_finite()) {
+            Ok(())
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "process node parameters must be finite",
+            ))
+        }
+    };
+
+    match node {
+        ProcessNode::Matrix { matrix, offset } => {
+            check_finite(&matrix.concat())?;
+            check_finite(offset)
+        }
+        ProcessNode::Lut1D(lut) => {
+            lut.validate()?;
+            check_table_count(lut.tables.len())?;
+            Ok(validate_table_lengths(&lut.tables, lut.tables[0].len())?)
+        }
+        ProcessNode::Lut1DHalfDomain(lut) => {
+            check_table_count(lut.tables.len())?;
+            Ok(validate_table_lengths(
+                &lut.tables,
+                Lut1DHalfDomain::SAMPLES,
+            )?)
+        }
+        ProcessNode::Lut3D(lut) => {
+            lut.validate()?;
+            if lut.resolution.iter().any(|r| *r != lut.resolution[0]) {
+                return Err(LutError::NonCubicResolution.into());
+            }
+            Ok(())
+        }
+        ProcessNode::Range {
+            min_in,
+            max_in,
+            min_out,
+            max_out,
+            ..
+        } => {
+            let values: Vec<f64> = [min_in, max_in, min_out, max_out]
+                .iter()
+                .filter_map(|v| **v)
+                .collect();
+            check_finite(&values)
+        }
+        ProcessNode::Log { params, .. } => {
+            for p in params.iter() {
+                check_finite(&[
+                    p.base,
+                    p.log_side_slope,
+                    p.log_side_offset,
+                    p.lin_side_slope,
+                    p.lin_side_offset,
+                    p.lin_side_break.unwrap_or(0.0),
+                    p.linear_slope.unwrap_or(0.0),
+                ])?;
+            }
+            Ok(())
+        }
+        ProcessNode::AscCdl {
+            slope,
+            offset,
+            power,
+            saturation,
+            ..
+        } => {
+            check_finite(slope)?;
+            check_finite(offset)?;
+            check_finite(power)?;
+            check_finite(&[*saturation])
+        }
+    }
+}
+
+fn write_matrix<W: Write>(
+    writer: &mut W,
+    matrix: &Matrix,
+    offset: &[f64; 3],
+) -> std::io::Result<()> {
+    let has_offset = offset.iter().any(|n| *n != 0.0);
+
+    writer.write_all(b"    <Matrix inBitDepth=\"32f\" outBitDepth=\"32f\">\n")?;
+    writer.write_all(
+        format!(
+            "        <Array dim=\"3 {}\">\n",
+            if has_offset { 4 } else { 3 }
+        )
+        .as_bytes(),
+    )?;
+    for (row, off) in matrix.iter().zip(offset.iter()) {
+        writer.write_all(format!("            {} {} {}", row[0], row[1], row[2]).as_bytes())?;
+        if has_offset {
+            writer.write_all(format!(" {}", off).as_bytes())?;
+        }
+        writer.write_all(b"\n")?;
+    }
+    writer.write_all(b"        </Array>\n")?;
+    writer.write_all(b"    </Matrix>\n")?;
+
+    Ok(())
+}
+
+/// Writes a matrix node that maps the given ranges to `[0.0, 1.0]`,
+/// unless they are already `[0.0, 1.0]`.
+fn write_range_normalizer<W: Write>(
+    writer: &mut W,
+    ranges: [(f32, f32); 3],
+) -> std::io::Result<()> {
+    if ranges.iter().all(|r| *r == (0.0, 1.0)) {
+        return Ok(());
+    }
+
+    let mut matrix = [[0.0f64; 3]; 3];
+    let mut offset = [0.0f64; 3];
+    for i in 0..3 {
+        let scale = 1.0 / (ranges[i].1 as f64 - ranges[i].0 as f64);
+        matrix[i][i] = scale;
+        offset[i] = -ranges[i].0 as f64 * scale;
+    }
+
+    write_matrix(writer, &matrix, &offset)
+}
+
+fn log_style_name(style: LogStyle) -> &'static str {
+    match style {
+        LogStyle::Log10 => "log10",
+        LogStyle::AntiLog10 => "antiLog10",
+        LogStyle::Log2 => "log2",
+        LogStyle::AntiLog2 => "antiLog2",
+        LogStyle::LinToLog => "linToLog",
+        LogStyle::LogToLin => "logToLin",
+        LogStyle::CameraLinToLog => "cameraLinToLog",
+        LogStyle::CameraLogToLin => "cameraLogToLin",
+    }
+}
+
+fn cdl_style_name(style: CdlStyle) -> &'static str {
+    match style {
+        CdlStyle::Fwd => "Fwd",
+        CdlStyle::Rev => "Rev",
+        CdlStyle::FwdNoClamp => "FwdNoClamp",
+        CdlStyle::RevNoClamp => "RevNoClamp",
+    }
+}
+
+/// Escapes text for use in XML attributes and character data.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+//-------------------------------------------------------------
+// Reading.
+
+/// Reads a CLF file.
+pub fn read<R: BufRead>(mut reader: R) -> Result<ProcessList, ReadError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    let root = parse_xml(&text)?;
+    if root.name != "ProcessList" {
+        return Err(ReadError::format(
+            root.line,
+            &root.name,
+            FormatErrorKind::BadKeyword,
+        ));
+    }
+
+    let mut process_list = ProcessList {
+        id: root.attr("id").unwrap_or("").into(),
+        name: root.attr("name").map(|s| s.into()),
+        description: Vec::new(),
+        nodes: Vec::new(),
+    };
+
+    for element in root.children.iter() {
+        let in_scale = element.bit_depth_scale("inBitDepth")?;
+        let out_scale = element.bit_depth_scale("outBitDepth")?;
+
+        match element.name.as_str() {
+            "Description" => process_list.description.push(element.text.trim().into()),
+
+            // Metadata that we don't do anything with.
+            "InputDescriptor" | "OutputDescriptor" | "Info" => {}
+
+            "Matrix" => {
+                let array = element.required_child("Array")?;
+                let dim = array.dim()?;
+                let columns = match dim.get(0..2) {
+                    Some([3, 3]) => 3,
+                    Some([3, 4]) => 4,
+                    _ => return Err(array.invalid_attr("dim")),
+                };
+                let values = array.values::<f64>(3 * columns)?;
+
+                let mut matrix = [[0.0f64; 3]; 3];
+                let mut offset = [0.0f64; 3];
+                for row in 0..3 {
+                    for col in 0..3 {
+                        matrix[row][col] =
+                            values[row * columns + col] * in_scale as f64 / out_scale as f64;
+                    }
+                    if columns == 4 {
+                        offset[row] = values[row * columns + 3] / out_scale as f64;
+                    }
+                }
+
+                process_list
+                    .nodes
+                    .push(ProcessNode::Matrix { matrix, offset });
+            }
+
+            "LUT1D" => {
+                let array = element.required_child("Array")?;
+                let (length, components) = match array.dim()?[..] {
+                    [len, comp] if len >= 2 && (comp == 1 || comp == 3) => (len, comp),
+                    _ => return Err(array.invalid_attr("dim")),
+                };
+                let half_domain = element.attr_bool("halfDomain")?;
+                let raw_halfs = element.attr_bool("rawHalfs")?;
+                let values = if raw_halfs {
+                    let mut values = Vec::with_capacity(length * components);
+                    for bits in array.values::<u16>(length * components)? {
+                        let n = f16_bits_to_f32(bits);
+                        if !n.is_finite() {
+                            return Err(ReadError::format(
+                                array.text_line,
+                                &bits.to_string(),
+                                FormatErrorKind::NonFiniteValue,
+                            ));
+                        }
+                        values.push(n);
+                    }
+                    values
+                } else {
+                    array
+                        .values::<f32>(length * components)?
+                        .iter()
+                        .map(|v| *v / out_scale)
+                        .collect()
+                };
+
+                if half_domain && length != Lut1DHalfDomain::SAMPLES {
+                    return Err(array.invalid_attr("dim"));
+                }
+
+                let mut tables = vec![Vec::with_capacity(length); components];
+                for entry in values.chunks_exact(components) {
+                    for (table, v) in tables.iter_mut().zip(entry.iter()) {
+                        table.push(*v);
+                    }
+                }
+
+                process_list.nodes.push(if half_domain {
+                    ProcessNode::Lut1DHalfDomain(Lut1DHalfDomain { tables })
+                } else {
+                    ProcessNode::Lut1D(Lut1D {
+                        ranges: vec![(0.0, 1.0)],
+                        tables,
+                    })
+                });
+            }
+
+            "LUT3D" => {
+                let array = element.required_child("Array")?;
+                let res = match array.dim()?[..] {
+                    [r1, r2, r3, 3] if r1 >= 2 && r1 == r2 && r2 == r3 => r1,
+                    _ => return Err(array.invalid_attr("dim")),
+                };
+                let values = array.values::<f32>(res * res * res * 3)?;
+
+                // CLF orders its 3D LUT data with blue changing fastest,
+                // which is the opposite of `Lut3D`.
+                let mut tables = vec![vec![0.0f32; res * res * res]; 3];
+                for (file_i, entry) in values.chunks_exact(3).enumerate() {
+                    let bi = file_i % res;
+                    let gi = (file_i / res) % res;
+                    let ri = file_i / (res * res);
+                    let i = ri + (gi * res) + (bi * res * res);
+                    for (table, v) in tables.iter_mut().zip(entry.iter()) {
+                        table[i] = *v / out_scale;
+                    }
+                }
+
+                process_list.nodes.push(ProcessNode::Lut3D(Lut3D {
+                    range: [(0.0, 1.0); 3],
+                    resolution: [res, res, res],
+                    tables,
+                }));
+            }
+
+            "Range" => {
+                let clamp = match element.attr("style") {
+                    None | Some("clamp") => true,
+                    Some("noClamp") => false,
+                    _ => return Err(element.invalid_attr("style")),
+                };
+                let value = |tag: &str, scale: f32| -> Result<Option<f64>, ReadError> {
+                    match element.child(tag) {
+                        Some(e) => Ok(Some(e.values::<f64>(1)?[0] / scale as f64)),
+                        None => Ok(None),
+                    }
+                };
+                let min_in = value("minInValue", in_scale)?;
+                let max_in = value("maxInValue", in_scale)?;
+                let min_out = value("minOutValue", out_scale)?;
+                let max_out = value("maxOutValue", out_scale)?;
+
+                // In and out values must come in pairs.
+                let missing = match (min_in, max_in, min_out, max_out) {
+                    (None, None, _, _) => Some("minInValue"),
+                    (Some(_), _, None, _) => Some("minOutValue"),
+                    (None, _, Some(_), _) => Some("minInValue"),
+                    (_, Some(_), _, None) => Some("maxOutValue"),
+                    (_, None, _, Some(_)) => Some("maxInValue"),
+                    _ => None,
+                };
+                if let Some(tag) = missing {
+                    return Err(ReadError::format(
+                        element.line,
+                        tag,
+                        FormatErrorKind::MissingKeyword,
+                    ));
+                }
+
+                process_list.nodes.push(ProcessNode::Range {
+                    min_in,
+                    max_in,
+                    min_out,
+                    max_out,
+                    clamp,
+                });
+            }
+
+            "Log" => {
+                let style = match element.attr("style") {
+                    Some("log10") => LogStyle::Log10,
+                    Some("antiLog10") => LogStyle::AntiLog10,
+                    Some("log2") => LogStyle::Log2,
+                    Some("antiLog2") => LogStyle::AntiLog2,
+                    Some("linToLog") => LogStyle::LinToLog,
+                    Some("logToLin") => LogStyle::LogToLin,
+                    Some("cameraLinToLog") => LogStyle::CameraLinToLog,
+                    Some("cameraLogToLin") => LogStyle::CameraLogToLin,
+                    _ => return Err(element.invalid_attr("style")),
+                };
+
+                let mut params = [LogParams::default(); 3];
+                for e in element.children.iter().filter(|e| e.name == "LogParams") {
+                    let mut p = LogParams::default();
+                    p.base = e.attr_number("base")?.unwrap_or(p.base);
+                    p.log_side_slope = e.attr_number("logSideSlope")?.unwrap_or(p.log_side_slope);
+                    p.log_side_offset =
+                        e.attr_number("logSideOffset")?.unwrap_or(p.log_side_offset);
+                    p.lin_side_slope = e.attr_number("linSideSlope")?.unwrap_or(p.lin_side_slope);
+                    p.lin_side_offset =
+                        e.attr_number("linSideOffset")?.unwrap_or(p.lin_side_offset);
+                    p.lin_side_break = e.attr_number("linSideBreak")?;
+                    p.linear_slope = e.attr_number("linearSlope")?;
+
+                    match e.attr("channel") {
+                        None => params = [p; 3],
+                        Some("R") => params[0] = p,
+                        Some("G") => params[1] = p,
+                        Some("B") => params[2] = p,
+                        _ => return Err(e.invalid_attr("channel")),
+                    }
+                }
+
+                // The camera styles require a break point.
+                if matches!(style, LogStyle::CameraLinToLog | LogStyle::CameraLogToLin)
+                    && params.iter().any(|p| p.lin_side_break.is_none())
+                {
+                    return Err(ReadError::format(
+                        element.line,
+                        "linSideBreak",
+                        FormatErrorKind::MissingKeyword,
+                    ));
+                }
+
+                process_list.nodes.push(ProcessNode::Log { style, params });
+            }
+
+            "ASC_CDL" => {
+                let style = match element.attr("style") {
+                    None | Some("Fwd") => CdlStyle::Fwd,
+                    Some("Rev") => CdlStyle::Rev,
+                    Some("FwdNoClamp") => CdlStyle::FwdNoClamp,
+                    Some("RevNoClamp") => CdlStyle::RevNoClamp,
+                    _ => return Err(element.invalid_attr("style")),
+                };
+
+                let mut slope = [1.0f64; 3];
+                let mut offset = [0.0f64; 3];
+                let mut power = [1.0f64; 3];
+                let mut saturation = 1.0f64;
+                if let Some(sop) = element.child("SOPNode") {
+                    for (tag, dest) in [
+                        ("Slope", &mut slope),
+                        ("Offset", &mut offset),
+                        ("Power", &mut power),
+                    ] {
+                        if let Some(e) = sop.child(tag) {
+                            let values = e.values::<f64>(3)?;
+                            *dest = [values[0], values[1], values[2]];
+                        }
+                    }
+                }
+                if let Some(e) = element.child("SatNode").and_then(|e| e.child("Saturation")) {
+                    saturation = e.values::<f64>(1)?[0];
+                }
+
+                process_list.nodes.push(ProcessNode::AscCdl {
+                    style,
+                    slope,
+                    offset,
+                    power,
+                    saturation,
+                });
+            }
+
+            _ => {
+                // Unknown or unsupported process node.
+                return Err(ReadError::format(
+                    element.line,
+                    &element.name,
+                    FormatErrorKind::BadKeyword,
+                ));
+            }
+        }
+    }
+
+    Ok(process_list)
+}
+
+//-------------------------------------------------------------
+// A minimal XML parser.
+//
+// This only supports what's needed for CLF files: elements, attributes,
+// character data, comments, and the standard entities.  Processing
+// instructions and doctype declarations are skipped.
+
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+
+    /// The line of the element's start tag.
+    line: usize,
+    /// The line where the element's character data starts.
+    text_line: usize,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|e| e.name == name)
+    }
+
+    fn required_child(&self, name: &str) -> Result<&Element, ReadError> {
+        self.child(name)
+            .ok_or_else(|| ReadError::format(self.line, name, FormatErrorKind::MissingKeyword))
+    }
+
+    fn invalid_attr(&self, name: &str) -> ReadError {
+        ReadError::format(
+            self.line,
+            self.attr(name).unwrap_or(name),
+            FormatErrorKind::InvalidValue,
+        )
+    }
+
+    /// Parses a boolean attribute, which is false if absent.
+    fn attr_bool(&self, name: &str) -> Result<bool, ReadError> {
+        match self.attr(name) {
+            None | Some("false") => Ok(false),
+            Some("true") => Ok(true),
+            Some(_) => Err(self.invalid_attr(name)),
+        }
+    }
+
+    fn attr_number(&self, name: &str) -> Result<Option<f64>, ReadError> {
+        self.attr(name)
+            .map(|value| parse_finite::<f64>(value.trim(), self.line))
+            .transpose()
+    }
+
+    /// Parses the `dim` attribute of an `Array` element.
+    fn dim(&self) -> Result<Vec<usize>, ReadError> {
+        self.attr("dim")
+            .ok_or_else(|| ReadError::format(self.line, "dim", FormatErrorKind::MissingKeyword))?
+            .split_whitespace()
+            .map(|n| parse::<usize>(n, self.line))
+            .collect()
+    }
+
+    /// Parses the character data as a list of exactly `count` numbers.
+    fn values<T: std::str::FromStr + Into<f64> + Copy>(
+        &self,
+        count: usize,
+    ) -> Result<Vec<T>, ReadError> {
+        let mut values = Vec::with_capacity(count);
+        for (i, line) in self.text.lines().enumerate() {
+            for token in line.split_whitespace() {
+                values.push(parse_finite::<T>(token, self.text_line + i)?);
+            }
+        }
+
+        if values.len() == count {
+            Ok(values)
+        } else if count == 1 || count == 3 {
+            Err(ReadError::format(
+                self.line,
+                &self.name,
+                FormatErrorKind::WrongValueCount {
+                    expected: count,
+                    found: values.len(),
+                },
+            ))
+        } else {
+            Err(ReadError::format(
+                self.line,
+                &self.name,
+                FormatErrorKind::SizeMismatch {
+                    expected: count,
+                    found: values.len(),
+                },
+            ))
+        }
+    }
+
+    /// Returns the value that represents 1.0 at the bit depth given by
+    /// the named attribute.
+    fn bit_depth_scale(&self, name: &str) -> Result<f32, ReadError> {
+        match self.attr(name) {
+            None | Some("16f") | Some("32f") => Ok(1.0),
+            Some("8i") => Ok(255.0),
+            Some("10i") => Ok(1023.0),
+            Some("12i") => Ok(4095.0),
+            Some("16i") => Ok(65535.0),
+            _ => Err(self.invalid_attr(name)),
+        }
+    }
+}
+
+/// Parses an XML document, returning its root element.
+fn parse_xml(text: &str) -> Result<Element, ReadError> {
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+    // Skip the UTF-8 byte order mark, if any.
+    let mut rest = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut line = 1;
+
+    let unexpected_end = |line| ReadError::format(line, "", FormatErrorKind::UnexpectedEnd);
+
+    while !rest.is_empty() {
+        // The length of the markup or character data handled this iteration.
+        let len;
+
+        if let Some(after) = rest.strip_prefix("<!--") {
+            let end = after.find("-->").ok_or_else(|| unexpected_end(line))?;
+            len = end + 7;
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            let end = rest.find('>').ok_or_else(|| unexpected_end(line))?;
+            len = end + 1;
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').ok_or_else(|| unexpected_end(line))?;
+            let name = after[..end].trim();
+            let element = match stack.pop() {
+                Some(element) if element.name == name => element,
+                _ => return Err(ReadError::format(line, name, FormatErrorKind::BadKeyword)),
+            };
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None if root.is_none() => root = Some(element),
+                None => return Err(ReadError::format(line, name, FormatErrorKind::BadKeyword)),
+            }
+            len = end + 3;
+        } else if let Some(after) = rest.strip_prefix('<') {
+            let end = find_tag_end(after).ok_or_else(|| unexpected_end(line))?;
+            let (tag, self_closing) = match after[..end].strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (&after[..end], false),
+            };
+            let mut element = parse_tag(tag, line)?;
+            element.line = line;
+            if self_closing {
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None if root.is_none() => root = Some(element),
+                    None => {
+                        return Err(ReadError::format(
+                            line,
+                            &element.name,
+                            FormatErrorKind::BadKeyword,
+                        ))
+                    }
+                }
+            } else {
+                stack.push(element);
+            }
+            len = end + 2;
+        } else {
+            // Character data.
+            let end = rest.find('<').unwrap_or(rest.len());
+            let data = &rest[..end];
+            match stack.last_mut() {
+                Some(element) => {
+                    if element.text.is_empty() {
+                        element.text_line = line;
+                    }
+                    element.text.push_str(&unescape(data, line)?);
+                }
+                None if data.trim().is_empty() => {}
+                None => {
+                    return Err(ReadError::format(
+                        line,
+                        data.trim(),
+                        FormatErrorKind::BadKeyword,
+                    ))
+                }
+            }
+            len = end;
+        }
+
+        line += rest[..len].matches('\n').count();
+        rest = &rest[len..];
+    }
+
+    if !stack.is_empty() {
+        return Err(unexpected_end(line));
+    }
+    root.ok_or_else(|| unexpected_end(line))
+}
+
+/// Finds the closing `>` of a tag, skipping over quoted attribute values.
+fn find_tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses the inside of a start tag: the element name and attributes.
+fn parse_tag(tag: &str, line: usize) -> Result<Element, ReadError> {
+    let malformed = || ReadError::format(line, tag.trim(), FormatErrorKind::BadKeyword);
+
+    let tag = tag.trim();
+    let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+    let mut element = Element {
+        name: tag[..name_end].into(),
+        ..Element::default()
+    };
+    if element.name.is_empty() {
+        return Err(malformed());
+    }
+
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=').ok_or_else(malformed)?;
+        let name = rest[..eq].trim();
+        let after = rest[(eq + 1)..].trim_start();
+        let quote = after.chars().next().ok_or_else(malformed)?;
+        if quote != '"' && quote != '\'' {
+            return Err(malformed());
+        }
+        let value_end = after[1..].find(quote).ok_or_else(malformed)? + 1;
+        element
+            .attributes
+            .push((name.into(), unescape(&after[1..value_end], line)?));
+        rest = after[(value_end + 1)..].trim_start();
+    }
+
+    Ok(element)
+}
+
+/// Replaces XML entities with the characters they represent.
+fn unescape(text: &str, line: usize) -> Result<String, ReadError> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(';')
+            .map(|end| end + start)
+            .ok_or_else(|| {
+                ReadError::format(line, &rest[start..], FormatErrorKind::InvalidValue)
+            })?;
+        let entity = &rest[(start + 1)..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse::<u32>().ok()
+                } else {
+                    None
+                };
+                code.and_then(char::from_u32).ok_or_else(|| {
+                    ReadError::format(line, &rest[start..=end], FormatErrorKind::InvalidValue)
+                })?
+            }
+        };
+        unescaped.push(c);
+        rest = &rest[(end + 1)..];
+    }
+    unescaped.push_str(rest);
+
+    Ok(unescaped)
+}
+
+//-------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_CLF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ProcessList compCLFversion="3.0" id="test-01" name="Test &amp; such">
+    <!-- A comment. -->
+    <Description>A test.</Description>
+    <Matrix inBitDepth="32f" outBitDepth="32f">
+        <Array dim="3 4">
+            1.0 0.5 0.0 0.1
+            0.0 1.0 0.0 0.2
+            0.0 0.0 2.0 0.3
+        </Array>
+    </Matrix>
+    <LUT1D inBitDepth="32f" outBitDepth="10i">
+        <Array dim="3 1">
+            0 511.5 1023
+        </Array>
+    </LUT1D>
+    <LUT3D inBitDepth="32f" outBitDepth="32f">
+        <Array dim="2 2 2 3">
+            0 0 0
+            0 0 1
+            0 1 0
+            0 1 1
+            1 0 0
+            1 0 1
+            1 1 0
+            1 1 1
+        </Array>
+    </LUT3D>
+    <Range>
+        <minInValue>0.25</minInValue>
+        <maxInValue>0.75</maxInValue>
+        <minOutValue>0</minOutValue>
+        <maxOutValue>1</maxOutValue>
+    </Range>
+    <Log style="cameraLinToLog">
+        <LogParams base="2" logSideSlope="0.5" logSideOffset="0.25" linSideSlope="1" linSideOffset="0.01" linSideBreak="0.1" />
+    </Log>
+    <ASC_CDL style="FwdNoClamp">
+        <SOPNode>
+            <Slope>1.1 1.0 0.9</Slope>
+            <Offset>0.01 0.0 -0.01</Offset>
+            <Power>1 1 1.2</Power>
+        </SOPNode>
+        <SatNode>
+            <Saturation>0.8</Saturation>
+        </SatNode>
+    </ASC_CDL>
+</ProcessList>
+"#;
+
+    fn check_test_clf(list: &ProcessList) {
+        assert_eq!(list.id, "test-01");
+        assert_eq!(list.name.as_deref(), Some("Test & such"));
+        assert_eq!(list.description, vec!["A test.".to_string()]);
+        assert_eq!(list.nodes.len(), 6);
+
+        match &list.nodes[0] {
+            ProcessNode::Matrix { matrix, offset } => {
+                assert_eq!(matrix, &[[1.0, 0.5, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 2.0]]);
+                assert_eq!(offset, &[0.1, 0.2, 0.3]);
+            }
+            _ => panic!("Expected a matrix node."),
+        }
+        match &list.nodes[1] {
+            ProcessNode::Lut1D(lut) => {
+                assert_eq!(lut.ranges, vec![(0.0, 1.0)]);
+                assert_eq!(lut.tables, vec![vec![0.0, 0.5, 1.0]]);
+            }
+            _ => panic!("Expected a 1D LUT node."),
+        }
+        match &list.nodes[2] {
+            ProcessNode::Lut3D(lut) => {
+                assert_eq!(lut.resolution, [2, 2, 2]);
+                // Should be an identity LUT after reordering.
+                for (i, channel) in lut.tables.iter().enumerate() {
+                    for (j, v) in channel.iter().enumerate() {
+                        let expected = (j >> i) & 1;
+                        assert_eq!(*v, expected as f32);
+                    }
+                }
+            }
+            _ => panic!("Expected a 3D LUT node."),
+        }
+        match &list.nodes[3] {
+            ProcessNode::Range {
+                min_in,
+                max_in,
+                min_out,
+                max_out,
+                clamp,
+            } => {
+                assert_eq!(*min_in, Some(0.25));
+                assert_eq!(*max_in, Some(0.75));
+                assert_eq!(*min_out, Some(0.0));
+                assert_eq!(*max_out, Some(1.0));
+                assert!(*clamp);
+            }
+            _ => panic!("Expected a range node."),
+        }
+        match &list.nodes[4] {
+            ProcessNode::Log { style, params } => {
+                assert_eq!(*style, LogStyle::CameraLinToLog);
+                for p in params.iter() {
+                    assert_eq!(p.log_side_slope, 0.5);
+                    assert_eq!(p.log_side_offset, 0.25);
+                    assert_eq!(p.lin_side_offset, 0.01);
+                    assert_eq!(p.lin_side_break, Some(0.1));
+                    assert_eq!(p.linear_slope, None);
+                }
+            }
+            _ => panic!("Expected a log node."),
+        }
+        match &list.nodes[5] {
+            ProcessNode::AscCdl {
+                style,
+                slope,
+                offset,
+                power,
+                saturation,
+            } => {
+                assert_eq!(*style, CdlStyle::FwdNoClamp);
+                assert_eq!(slope, &[1.1, 1.0, 0.9]);
+                assert_eq!(offset, &[0.01, 0.0, -0.01]);
+                assert_eq!(power, &[1.0, 1.0, 1.2]);
+                assert_eq!(*saturation, 0.8);
+            }
+            _ => panic!("Expected an ASC CDL node."),
+        }
+    }
+
+    #[test]
+    fn read_01() {
+        let list = read(TEST_CLF.as_bytes()).unwrap();
+        check_test_clf(&list);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let list = read(TEST_CLF.as_bytes()).unwrap();
+        let mut data = Vec::new();
+        write(&mut data, &list).unwrap();
+        let list = read(&data[..]).unwrap();
+        check_test_clf(&list);
+    }
+
+    #[test]
+    fn read_bad_xml() {
+        assert!(read(&b"<ProcessList id=\"a\"><Matrix></ProcessList>"[..]).is_err());
+        assert!(read(&b"<ProcessList id=\"a\">"[..]).is_err());
+        assert!(read(&b"<NotAProcessList></NotAProcessList>"[..]).is_err());
+    }
+
+    #[test]
+    fn read_bom() {
+        let text = format!("\u{feff}{}", TEST_CLF);
+        let list = read(text.as_bytes()).unwrap();
+        check_test_clf(&list);
+    }
+
+    #[test]
+    fn write_non_finite() {
+        // Non-finite LUT values are written as zero.
+        let list = ProcessList {
+            id: "nan".into(),
+            nodes: vec![ProcessNode::Lut1D(Lut1D {
+                ranges: vec![(0.0, 1.0)],
+                tables: vec![vec![0.0, f32::NAN, f32::INFINITY]],
+            })],
+            ..ProcessList::default()
+        };
+        let mut data = Vec::new();
+        write(&mut data, &list).unwrap();
+        let list = read(&data[..]).unwrap();
+        match &list.nodes[0] {
+            ProcessNode::Lut1D(lut) => assert_eq!(lut.tables[0], vec![0.0, 0.0, 0.0]),
+            _ => panic!("Expected a 1D LUT node."),
+        }
+
+        // Non-finite parameters are rejected.
+        let list = ProcessList {
+            id: "nan".into(),
+            nodes: vec![ProcessNode::Matrix {
+                matrix: [[1.0, 0.0, 0.0], [0.0, f64::NAN, 0.0], [0.0, 0.0, 1.0]],
+                offset: [0.0; 3],
+            }],
+            ..ProcessList::default()
+        };
+        let mut data = Vec::new();
+        let err = write(&mut data, &list).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn half_domain_round_trip() {
+        use crate::transfer_functions::srgb;
+
+        let lut = Lut1DHalfDomain::from_fn_1(srgb::from_linear);
+        let list = ProcessList {
+            id: "half".into(),
+            nodes: vec![ProcessNode::Lut1DHalfDomain(lut.clone())],
+            ..ProcessList::default()
+        };
+        let mut data = Vec::new();
+        write(&mut data, &list).unwrap();
+        let list = read(&data[..]).unwrap();
+
+        match &list.nodes[0] {
+            ProcessNode::Lut1DHalfDomain(lut2) => assert_eq!(lut2.tables, lut.tables),
+            _ => panic!("Expected a half-domain 1D LUT node."),
+        }
+    }
+
+    #[test]
+    fn read_raw_halfs() {
+        // An identity LUT with its values given as raw f16 bits.
+        let values: Vec<String> = (0..Lut1DHalfDomain::SAMPLES)
+            .map(|bits| {
+                let n = crate::lut::f16_bits_to_f32(bits as u16);
+                if n.is_finite() { bits } else { 0 }.to_string()
+            })
+            .collect();
+        let text = format!(
+            "<ProcessList id=\"a\">\
+             <LUT1D inBitDepth=\"16f\" outBitDepth=\"16f\" halfDomain=\"true\" rawHalfs=\"true\">\
+             <Array dim=\"65536 1\">{}</Array></LUT1D></ProcessList>",
+            values.join(" ")
+        );
+        let list = read(text.as_bytes()).unwrap();
+        match &list.nodes[0] {
+            ProcessNode::Lut1DHalfDomain(lut) => {
+                assert_eq!(lut.look_up(0.5, 0), 0.5);
+                assert_eq!(lut.look_up(-1234.5, 0), -1234.5);
+            }
+            _ => panic!("Expected a half-domain 1D LUT node."),
+        }
+
+        // Wrong size for a half-domain LUT.
+        let text = "<ProcessList id=\"a\"><LUT1D halfDomain=\"true\">\
+                    <Array dim=\"2 1\">0 1</Array></LUT1D></ProcessList>";
+        assert!(read(text.as_bytes()).is_err());
+    }
+}
@@ -3,39 +3,43 @@
 //! This format can contain either a 1D LUT or a 3D LUT, but not both,
 //! and there is no indication in the file extension which is which.
 
-use std::io::{BufRead, Write};
+use std::io::{BufRead, BufWriter, Write};
 
-use super::filter_non_finite;
-use crate::lut::{Lut1D, Lut3D};
+use super::{
+    expect_values, is_numeric_token, parse, parse_cube_comment, parse_cube_title, parse_finite,
+    write_cube_metadata, CubeMetadata, FormatErrorKind, LineReader, ReadError, Tokens,
+    WriteOptions, WRITE_BUFFER_SIZE,
+};
+use crate::lut::{validate_1d, validate_3d, validate_table_lengths, Lut1D, Lut3D};
 
 /// Writes a 1D .cube file.
+///
+/// The tables must all have the same length.  Invalid LUT data is
+/// reported as an error of kind `InvalidInput`.
+///
+/// The title and comments in `metadata` are written at the top of the
+/// file.  The title must not contain double quotes or line breaks.
+///
+/// `options` controls the number formatting and line endings.
 pub fn write_1d<W: Write>(
-    mut writer: W,
+    writer: W,
+    metadata: &CubeMetadata,
     ranges: [(f32, f32); 3],
     tables: [&[f32]; 3],
+    options: &WriteOptions,
 ) -> std::io::Result<()> {
-    assert!(tables[0].len() == tables[1].len() && tables[1].len() == tables[2].len());
-
-    writer.write_all(b"TITLE \"untitled\"\n")?;
-    writer.write_all(
-        format!(
-            "DOMAIN_MIN {} {} {}\n",
-            filter_non_finite(ranges[0].0),
-            filter_non_finite(ranges[1].0),
-            filter_non_finite(ranges[2].0),
-        )
-        .as_bytes(),
-    )?;
-    writer.write_all(
-        format!(
-            "DOMAIN_MAX {} {} {}\n",
-            filter_non_finite(ranges[0].1),
-            filter_non_finite(ranges[1].1),
-            filter_non_finite(ranges[2].1),
-        )
-        .as_bytes(),
-    )?;
-    writer.write_all(format!("LUT_1D_SIZE {}\n", tables[0].len()).as_bytes())?;
+    validate_1d(&ranges, &tables)?;
+    validate_table_lengths(&tables, tables[0].len())?;
+
+    let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, writer);
+
+    write_cube_metadata(&mut writer, metadata, options)?;
+    writer.write_all(b"DOMAIN_MIN ")?;
+    options.write_values(&mut writer, &[ranges[0].0, ranges[1].0, ranges[2].0])?;
+    writer.write_all(b"DOMAIN_MAX ")?;
+    options.write_values(&mut writer, &[ranges[0].1, ranges[1].1, ranges[2].1])?;
+    write!(writer, "LUT_1D_SIZE {}", tables[0].len())?;
+    options.end_line(&mut writer)?;
 
     for ((r, g), b) in tables[0]
         .iter()
@@ -43,53 +47,41 @@ pub fn write_1d<W: Write>(
         .zip(tables[1].iter().copied())
         .zip(tables[2].iter().copied())
     {
-        writer.write_all(
-            format!(
-                "{} {} {}\n",
-                filter_non_finite(r),
-                filter_non_finite(g),
-                filter_non_finite(b),
-            )
-            .as_bytes(),
-        )?;
+        options.write_values(&mut writer, &[r, g, b])?;
     }
 
-    Ok(())
+    writer.flush()
 }
 
 /// Writes a 3D .cube file.
 ///
 /// The tables should have a length of `resolution * resolution * resolution`,
 /// and their indices should be ordered the same as the `Lut3D` type.
+/// Invalid LUT data is reported as an error of kind `InvalidInput`.
+///
+/// The title and comments in `metadata` are written at the top of the
+/// file.  The title must not contain double quotes or line breaks.
+///
+/// `options` controls the number formatting and line endings.
 pub fn write_3d<W: Write>(
-    mut writer: W,
+    writer: W,
+    metadata: &CubeMetadata,
     ranges: [(f32, f32); 3],
     resolution: usize,
     tables: [&[f32]; 3],
+    options: &WriteOptions,
 ) -> std::io::Result<()> {
-    assert!(tables[0].len() == (resolution * resolution * resolution));
-    assert!(tables[0].len() == tables[1].len() && tables[1].len() == tables[2].len());
-
-    writer.write_all(b"TITLE \"untitled\"\n")?;
-    writer.write_all(
-        format!(
-            "DOMAIN_MIN {} {} {}\n",
-            filter_non_finite(ranges[0].0),
-            filter_non_finite(ranges[1].0),
-            filter_non_finite(ranges[2].0),
-        )
-        .as_bytes(),
-    )?;
-    writer.write_all(
-        format!(
-            "DOMAIN_MAX {} {} {}\n",
-            filter_non_finite(ranges[0].1),
-            filter_non_finite(ranges[1].1),
-            filter_non_finite(ranges[2].1),
-        )
-        .as_bytes(),
-    )?;
-    writer.write_all(format!("LUT_3D_SIZE {}\n", resolution).as_bytes())?;
+    validate_3d(&ranges, [resolution; 3], &tables)?;
+
+    let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, writer);
+
+    write_cube_metadata(&mut writer, metadata, options)?;
+    writer.write_all(b"DOMAIN_MIN ")?;
+    options.write_values(&mut writer, &[ranges[0].0, ranges[1].0, ranges[2].0])?;
+    writer.write_all(b"DOMAIN_MAX ")?;
+    options.write_values(&mut writer, &[ranges[0].1, ranges[1].1, ranges[2].1])?;
+    write!(writer, "LUT_3D_SIZE {}", resolution)?;
+    options.end_line(&mut writer)?;
 
     for ((r, g), b) in tables[0]
         .iter()
@@ -97,139 +89,145 @@ pub fn write_3d<W: Write>(
         .zip(tables[1].iter().copied())
         .zip(tables[2].iter().copied())
     {
-        writer.write_all(
-            format!(
-                "{} {} {}\n",
-                filter_non_finite(r),
-                filter_non_finite(g),
-                filter_non_finite(b),
-            )
-            .as_bytes(),
-        )?;
+        options.write_values(&mut writer, &[r, g, b])?;
     }
 
-    Ok(())
+    writer.flush()
 }
 
 /// Reads a 1D .cube file.
-pub fn read_1d<R: BufRead>(reader: R) -> Result<Lut1D, super::ReadError> {
-    // let mut name: Option<String> = None;
-    let mut ranges = [(0.0f32, 1.0f32); 3];
-    let mut length = None;
-    let mut tables = [Vec::new(), Vec::new(), Vec::new()];
-
-    for line in reader.lines() {
-        let line = line?;
-        let parts: Vec<_> = line.split_whitespace().collect();
+///
+/// Returns the LUT and the file's title and comments.
+pub fn read_1d<R: BufRead>(reader: R) -> Result<(Lut1D, CubeMetadata), ReadError> {
+    let (metadata, ranges, _, size_line, tables) = read_cube(reader, "LUT_1D_SIZE", Some)?;
 
-        if parts.is_empty() || parts[0].starts_with("#") {
-            continue;
-        } else if parts[0] == "TITLE" && parts.len() > 1 {
-            let name_parts: Vec<_> = line.trim().split("\"").collect();
-            if name_parts.len() != 3 || !name_parts[2].is_empty() {
-                return Err(super::ReadError::FormatErr);
-            }
-            // name = Some(name_parts[1].into());
-            continue;
-        } else if parts[0] == "DOMAIN_MIN" && parts.len() == 4 {
-            ranges[0].0 = parts[1].parse::<f32>()?;
-            ranges[1].0 = parts[2].parse::<f32>()?;
-            ranges[2].0 = parts[3].parse::<f32>()?;
-            continue;
-        } else if parts[0] == "DOMAIN_MAX" && parts.len() == 4 {
-            ranges[0].1 = parts[1].parse::<f32>()?;
-            ranges[1].1 = parts[2].parse::<f32>()?;
-            ranges[2].1 = parts[3].parse::<f32>()?;
-            continue;
-        } else if parts[0] == "LUT_1D_SIZE" && parts.len() == 2 {
-            length = Some(parts[1].parse::<usize>()?);
-            continue;
-        } else if parts.len() == 3 {
-            tables[0].push(parts[0].parse::<f32>()?);
-            tables[1].push(parts[1].parse::<f32>()?);
-            tables[2].push(parts[2].parse::<f32>()?);
-            continue;
-        } else {
-            // Line didn't match any acceptable pattern.
-            return Err(super::ReadError::FormatErr);
-        }
-    }
+    let [table_r, table_g, table_b] = tables;
+    let lut = Lut1D {
+        ranges: vec![ranges[0], ranges[1], ranges[2]],
+        tables: vec![table_r, table_g, table_b],
+    };
+    lut.validate()
+        .map_err(|e| ReadError::invalid_lut(size_line, "LUT_1D_SIZE", e))?;
+
+    Ok((lut, metadata))
+}
 
-    if !tables.iter().flatten().all(|n| n.is_finite())
-        || !ranges.iter().all(|(a, b)| a.is_finite() && b.is_finite())
-    {
-        // Non-finite values in the file.
-        return Err(super::ReadError::FormatErr);
-    }
+/// Reads a 3D .cube file.
+///
+/// Returns the LUT and the file's title and comments.
+pub fn read_3d<R: BufRead>(reader: R) -> Result<(Lut3D, CubeMetadata), ReadError> {
+    let (metadata, ranges, res, size_line, tables) = read_cube(reader, "LUT_3D_SIZE", |res| {
+        res.checked_mul(res).and_then(|n| n.checked_mul(res))
+    })?;
 
     let [table_r, table_g, table_b] = tables;
-    match length {
-        Some(len) if len == table_r.len() => Ok(Lut1D {
-            ranges: vec![ranges[0], ranges[1], ranges[2]],
-            tables: vec![table_r, table_g, table_b],
-        }),
-        _ => Err(super::ReadError::FormatErr),
-    }
+    let lut = Lut3D {
+        range: ranges,
+        resolution: [res, res, res],
+        tables: vec![table_r, table_g, table_b],
+    };
+    lut.validate()
+        .map_err(|e| ReadError::invalid_lut(size_line, "LUT_3D_SIZE", e))?;
+
+    Ok((lut, metadata))
 }
 
-/// Reads a 3D .cube file.
-pub fn read_3d<R: BufRead>(reader: R) -> Result<Lut3D, super::ReadError> {
-    // let mut name: Option<String> = None;
+/// Shared reading code for 1D and 3D .cube files, since they only differ
+/// in their size keyword.
+///
+/// `entry_count` computes the expected number of table entries from the
+/// size specified in the file, or returns `None` if it overflows.
+///
+/// Returns the metadata, the ranges, the size, the line number of the
+/// size keyword, and the tables.
+#[allow(clippy::type_complexity)]
+fn read_cube<R: BufRead, F: Fn(usize) -> Option<usize>>(
+    reader: R,
+    size_keyword: &str,
+    entry_count: F,
+) -> Result<(CubeMetadata, [(f32, f32); 3], usize, usize, [Vec<f32>; 3]), ReadError> {
+    let mut metadata = CubeMetadata::default();
     let mut ranges = [(0.0f32, 1.0f32); 3];
-    let mut resolution = None;
+    let mut size = None;
     let mut tables = [Vec::new(), Vec::new(), Vec::new()];
 
-    for line in reader.lines() {
-        let line = line?;
-        let parts: Vec<_> = line.split_whitespace().collect();
+    let mut lines = LineReader::new(reader);
+    let mut line_n = 0;
+    while let Some((n, line)) = lines.next_line()? {
+        line_n = n;
+        let parts = Tokens::new(line);
 
-        if parts.is_empty() || parts[0].starts_with("#") {
+        // Data lines are by far the most common, so check for them first.
+        if parts.is_empty() {
             continue;
-        } else if parts[0] == "TITLE" && parts.len() > 1 {
-            let name_parts: Vec<_> = line.trim().split("\"").collect();
-            if name_parts.len() != 3 || !name_parts[2].is_empty() {
-                return Err(super::ReadError::FormatErr);
+        } else if is_numeric_token(parts[0]) {
+            if parts.len() != 3 {
+                return Err(ReadError::format(
+                    line_n,
+                    line.trim(),
+                    FormatErrorKind::WrongValueCount {
+                        expected: 3,
+                        found: parts.len(),
+                    },
+                ));
             }
-            // name = Some(name_parts[1].into());
-            continue;
-        } else if parts[0] == "DOMAIN_MIN" && parts.len() == 4 {
-            ranges[0].0 = parts[1].parse::<f32>()?;
-            ranges[1].0 = parts[2].parse::<f32>()?;
-            ranges[2].0 = parts[3].parse::<f32>()?;
-            continue;
-        } else if parts[0] == "DOMAIN_MAX" && parts.len() == 4 {
-            ranges[0].1 = parts[1].parse::<f32>()?;
-            ranges[1].1 = parts[2].parse::<f32>()?;
-            ranges[2].1 = parts[3].parse::<f32>()?;
-            continue;
-        } else if parts[0] == "LUT_3D_SIZE" && parts.len() == 2 {
-            resolution = Some(parts[1].parse::<usize>()?);
-            continue;
-        } else if parts.len() == 3 {
-            tables[0].push(parts[0].parse::<f32>()?);
-            tables[1].push(parts[1].parse::<f32>()?);
-            tables[2].push(parts[2].parse::<f32>()?);
-            continue;
+            tables[0].push(parse_finite(parts[0], line_n)?);
+            tables[1].push(parse_finite(parts[1], line_n)?);
+            tables[2].push(parse_finite(parts[2], line_n)?);
+        } else if let Some(comment) = parse_cube_comment(line) {
+            metadata.comments.push(comment);
+        } else if parts[0] == "TITLE" {
+            metadata.title = Some(parse_cube_title(line, line_n)?);
+        } else if parts[0] == "DOMAIN_MIN" {
+            expect_values(&parts, 3, line_n)?;
+            ranges[0].0 = parse_finite(parts[1], line_n)?;
+            ranges[1].0 = parse_finite(parts[2], line_n)?;
+            ranges[2].0 = parse_finite(parts[3], line_n)?;
+        } else if parts[0] == "DOMAIN_MAX" {
+            expect_values(&parts, 3, line_n)?;
+            ranges[0].1 = parse_finite(parts[1], line_n)?;
+            ranges[1].1 = parse_finite(parts[2], line_n)?;
+            ranges[2].1 = parse_finite(parts[3], line_n)?;
+        } else if parts[0] == size_keyword {
+            expect_values(&parts, 1, line_n)?;
+            let n = parse::<usize>(parts[1], line_n)?;
+            let count = match entry_count(n) {
+                Some(count) => count,
+                None => {
+                    return Err(ReadError::format(
+                        line_n,
+                        parts[1],
+                        FormatErrorKind::InvalidValue,
+                    ))
+                }
+            };
+            size = Some((n, count, line_n));
         } else {
             // Line didn't match any acceptable pattern.
-            return Err(super::ReadError::FormatErr);
+            return Err(ReadError::format(
+                line_n,
+                parts[0],
+                FormatErrorKind::BadKeyword,
+            ));
         }
     }
 
-    if !tables.iter().flatten().all(|n| n.is_finite())
-        || !ranges.iter().all(|(a, b)| a.is_finite() && b.is_finite())
-    {
-        // Non-finite values in the file.
-        return Err(super::ReadError::FormatErr);
-    }
-
-    let [table_r, table_g, table_b] = tables;
-    match resolution {
-        Some(res) if (res * res * res) == table_r.len() => Ok(Lut3D {
-            range: ranges,
-            resolution: [res, res, res],
-            tables: vec![table_r, table_g, table_b],
-        }),
-        _ => Err(super::ReadError::FormatErr),
+    match size {
+        Some((size, count, size_line)) if count == tables[0].len() => {
+            Ok((metadata, ranges, size, size_line, tables))
+        }
+        Some((_, count, size_line)) => Err(ReadError::format(
+            size_line,
+            size_keyword,
+            FormatErrorKind::SizeMismatch {
+                expected: count,
+                found: tables[0].len(),
+            },
+        )),
+        None => Err(ReadError::format(
+            line_n,
+            size_keyword,
+            FormatErrorKind::MissingKeyword,
+        )),
     }
 }
@@ -11,52 +11,67 @@
 // Implemented according to:
 // https://web.archive.org/web/20201125231728/https://forum.blackmagicdesign.com/viewtopic.php?f=21&t=40284
 
-use std::io::{BufRead, Write};
+use std::io::{BufRead, BufWriter, Write};
 
-use super::filter_non_finite;
-use crate::lut::{Lut1D, Lut3D};
+use super::{
+    expect_values, is_numeric_token, parse, parse_cube_comment, parse_cube_title, parse_finite,
+    write_cube_metadata, CubeMetadata, FormatErrorKind, LineReader, ReadError, Tokens,
+    WriteOptions, WRITE_BUFFER_SIZE,
+};
+use crate::lut::{validate_1d, validate_3d, validate_table_lengths, Lut1D, Lut3D};
 
 /// Writes a .cube file.
 ///
 /// Can contain either a 1D LUT, a 3D LUT, or both.  But must have at
-/// least one of the two.
+/// least one of the two.  Invalid LUT data is reported as an error of
+/// kind `InvalidInput`.
 ///
 /// - `lut_1d`: (range_min, range_max, tables [r, g, b])
 /// - `lut_3d`: (range_min, range_max, resolution, tables [r, g, b]).
 ///             The tables should have indices ordered the same as the
 ///             `Lut3D` type.
+///
+/// The title and comments in `metadata` are written at the top of the
+/// file.  The title must not contain double quotes or line breaks.
+///
+/// `options` controls the number formatting and line endings.
 pub fn write<W: Write>(
-    mut writer: W,
+    writer: W,
+    metadata: &CubeMetadata,
     lut_1d: Option<(f32, f32, [&[f32]; 3])>,
     lut_3d: Option<(f32, f32, usize, [&[f32]; 3])>,
+    options: &WriteOptions,
 ) -> std::io::Result<()> {
-    assert!(!(lut_1d.is_none() && lut_3d.is_none()));
-
-    // Write header and do basic sanity checks.
+    if lut_1d.is_none() && lut_3d.is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "a .cube file must contain at least one LUT",
+        ));
+    }
     if let Some((range_min, range_max, tables)) = lut_1d {
-        assert!(tables[0].len() == tables[1].len() && tables[1].len() == tables[2].len());
-        writer.write_all(format!("LUT_1D_SIZE {}\n", tables[0].len()).as_bytes())?;
-        writer.write_all(
-            format!(
-                "LUT_1D_INPUT_RANGE {} {}\n",
-                filter_non_finite(range_min),
-                filter_non_finite(range_max),
-            )
-            .as_bytes(),
-        )?;
+        validate_1d(&[(range_min, range_max)], &tables)?;
+        validate_table_lengths(&tables, tables[0].len())?;
     }
     if let Some((range_min, range_max, res, tables)) = lut_3d {
-        assert!(tables[0].len() == (res * res * res));
-        assert!(tables[0].len() == tables[1].len() && tables[1].len() == tables[2].len());
-        writer.write_all(format!("LUT_3D_SIZE {}\n", res).as_bytes())?;
-        writer.write_all(
-            format!(
-                "LUT_3D_INPUT_RANGE {} {}\n",
-                filter_non_finite(range_min),
-                filter_non_finite(range_max),
-            )
-            .as_bytes(),
-        )?;
+        validate_3d(&[(range_min, range_max); 3], [res; 3], &tables)?;
+    }
+
+    let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, writer);
+
+    write_cube_metadata(&mut writer, metadata, options)?;
+
+    // Write header.
+    if let Some((range_min, range_max, tables)) = lut_1d {
+        write!(writer, "LUT_1D_SIZE {}", tables[0].len())?;
+        options.end_line(&mut writer)?;
+        writer.write_all(b"LUT_1D_INPUT_RANGE ")?;
+        options.write_values(&mut writer, &[range_min, range_max])?;
+    }
+    if let Some((range_min, range_max, res, _)) = lut_3d {
+        write!(writer, "LUT_3D_SIZE {}", res)?;
+        options.end_line(&mut writer)?;
+        writer.write_all(b"LUT_3D_INPUT_RANGE ")?;
+        options.write_values(&mut writer, &[range_min, range_max])?;
     }
 
     // Write LUT data.
@@ -67,15 +82,7 @@ pub fn write<W: Write>(
             .zip(tables[1].iter().copied())
             .zip(tables[2].iter().copied())
         {
-            writer.write_all(
-                format!(
-                    "{} {} {}\n",
-                    filter_non_finite(r),
-                    filter_non_finite(g),
-                    filter_non_finite(b),
-                )
-                .as_bytes(),
-            )?;
+            options.write_values(&mut writer, &[r, g, b])?;
         }
     }
     if let Some((_, _, _, tables)) = lut_3d {
@@ -85,142 +92,258 @@ pub fn write<W: Write>(
             .zip(tables[1].iter().copied())
             .zip(tables[2].iter().copied())
         {
-            writer.write_all(
-                format!(
-                    "{} {} {}\n",
-                    filter_non_finite(r),
-                    filter_non_finite(g),
-                    filter_non_finite(b),
-                )
-                .as_bytes(),
-            )?;
+            options.write_values(&mut writer, &[r, g, b])?;
         }
     }
 
-    Ok(())
+    writer.flush()
 }
 
 /// Reads a .cube file.
 ///
-/// Either a 1D LUT, a 3D LUT, or both can be returned.
-pub fn read<R: BufRead>(reader: R) -> Result<(Option<Lut1D>, Option<Lut3D>), super::ReadError> {
-    // let mut name: Option<String> = None;
+/// Either a 1D LUT, a 3D LUT, or both can be returned, along with the
+/// file's title and comments.
+pub fn read<R: BufRead>(
+    reader: R,
+) -> Result<(Option<Lut1D>, Option<Lut3D>, CubeMetadata), ReadError> {
+    let mut metadata = CubeMetadata::default();
     let mut range_1d = (0.0f32, 1.0f32);
     let mut length_1d = 0;
+    let mut length_1d_line = 0;
     let mut tables_1d = [Vec::new(), Vec::new(), Vec::new()];
 
     let mut range_3d = (0.0f32, 1.0f32);
     let mut size_3d = 0;
+    let mut size_3d_line = 0;
+    let mut length_3d = 0;
     let mut tables_3d = [Vec::new(), Vec::new(), Vec::new()];
 
-    let mut lines = reader.lines().peekable();
+    let mut lines = LineReader::new(reader);
+    let mut line_n = 0;
+    let mut in_header = true;
+    let mut extra_entries = 0;
+    let mut extra_line = 0;
 
-    // Parse header.
-    while let Some(line) = lines.peek() {
-        let line = match line {
-            &Ok(ref s) => s,
-            &Err(_) => break, // Will be caught later.
-        };
-        let parts: Vec<_> = line.split_whitespace().collect();
-
-        if parts.is_empty() || parts[0].starts_with("#") {
-            // Skip blank lines and comments.
-        } else if parts[0] == "TITLE" && parts.len() > 1 {
-            let name_parts: Vec<_> = line.trim().split("\"").collect();
-            if name_parts.len() != 3 || !name_parts[2].is_empty() {
-                return Err(super::ReadError::FormatErr);
-            }
-            // name = Some(name_parts[1].into());
-        } else if parts[0] == "LUT_1D_SIZE" && parts.len() == 2 {
-            length_1d = parts[1].parse::<usize>()?;
-        } else if parts[0] == "LUT_1D_INPUT_RANGE" && parts.len() == 3 {
-            range_1d.0 = parts[1].parse::<f32>()?;
-            range_1d.1 = parts[2].parse::<f32>()?;
-        } else if parts[0] == "LUT_3D_SIZE" && parts.len() == 2 {
-            size_3d = parts[1].parse::<usize>()?;
-        } else if parts[0] == "LUT_3D_INPUT_RANGE" && parts.len() == 3 {
-            range_3d.0 = parts[1].parse::<f32>()?;
-            range_3d.1 = parts[2].parse::<f32>()?;
-        } else {
-            // Non-header line encountered.  End of header.
-            break;
+    while let Some((n, line)) = lines.next_line()? {
+        line_n = n;
+        let parts = Tokens::new(line);
+
+        if parts.is_empty() {
+            // Skip blank lines.
+            continue;
+        } else if let Some(comment) = parse_cube_comment(line) {
+            metadata.comments.push(comment);
+            continue;
         }
 
-        lines.next();
-    }
+        if in_header {
+            if parts[0] == "TITLE" {
+                metadata.title = Some(parse_cube_title(line, line_n)?);
+                continue;
+            } else if parts[0] == "LUT_1D_SIZE" {
+                expect_values(&parts, 1, line_n)?;
+                length_1d = parse::<usize>(parts[1], line_n)?;
+                length_1d_line = line_n;
+                continue;
+            } else if parts[0] == "LUT_1D_INPUT_RANGE" {
+                expect_values(&parts, 2, line_n)?;
+                range_1d.0 = parse_finite(parts[1], line_n)?;
+                range_1d.1 = parse_finite(parts[2], line_n)?;
+                continue;
+            } else if parts[0] == "LUT_3D_SIZE" {
+                expect_values(&parts, 1, line_n)?;
+                size_3d = parse::<usize>(parts[1], line_n)?;
+                size_3d_line = line_n;
+                length_3d = match size_3d
+                    .checked_mul(size_3d)
+                    .and_then(|n| n.checked_mul(size_3d))
+                {
+                    Some(length) => length,
+                    None => {
+                        return Err(ReadError::format(
+                            line_n,
+                            parts[1],
+                            FormatErrorKind::InvalidValue,
+                        ))
+                    }
+                };
+                continue;
+            } else if parts[0] == "LUT_3D_INPUT_RANGE" {
+                expect_values(&parts, 2, line_n)?;
+                range_3d.0 = parse_finite(parts[1], line_n)?;
+                range_3d.1 = parse_finite(parts[2], line_n)?;
+                continue;
+            }
 
-    // Check for invalid header.
-    if length_1d == 0 && size_3d == 0 {
-        return Err(super::ReadError::FormatErr);
-    }
+            // Non-header line encountered.  End of header.
+            in_header = false;
 
-    let mut length_3d = size_3d * size_3d * size_3d;
-
-    // Parse LUT data.
-    for line in lines {
-        let line = line?;
-        let parts: Vec<_> = line.split_whitespace().collect();
-
-        if parts.is_empty() || parts[0].starts_with("#") {
-            // Skip blank lines and comments.
-        } else if parts.len() == 3 {
-            if length_1d > 0 {
-                tables_1d[0].push(parts[0].parse::<f32>()?);
-                tables_1d[1].push(parts[1].parse::<f32>()?);
-                tables_1d[2].push(parts[2].parse::<f32>()?);
-                length_1d -= 1;
-            } else if length_3d > 0 {
-                tables_3d[0].push(parts[0].parse::<f32>()?);
-                tables_3d[1].push(parts[1].parse::<f32>()?);
-                tables_3d[2].push(parts[2].parse::<f32>()?);
-                length_3d -= 1;
-            } else {
-                // Should have reached the end already.
-                return Err(super::ReadError::FormatErr);
+            // Check for invalid header.
+            if length_1d == 0 && size_3d == 0 {
+                return Err(ReadError::format(
+                    line_n,
+                    "LUT_1D_SIZE",
+                    FormatErrorKind::MissingKeyword,
+                ));
             }
-        } else {
+        }
+
+        // Parse LUT data.
+        if !is_numeric_token(parts[0]) {
             // Line didn't match any acceptable pattern.
-            return Err(super::ReadError::FormatErr);
+            return Err(ReadError::format(
+                line_n,
+                parts[0],
+                FormatErrorKind::BadKeyword,
+            ));
+        } else if parts.len() != 3 {
+            return Err(ReadError::format(
+                line_n,
+                line.trim(),
+                FormatErrorKind::WrongValueCount {
+                    expected: 3,
+                    found: parts.len(),
+                },
+            ));
         }
+        let tables = if tables_1d[0].len() < length_1d {
+            &mut tables_1d
+        } else if tables_3d[0].len() < length_3d {
+            &mut tables_3d
+        } else {
+            // Should have reached the end already.  Keep counting
+            // so we can report the total.
+            if extra_entries == 0 {
+                extra_line = line_n;
+            }
+            extra_entries += 1;
+            continue;
+        };
+        tables[0].push(parse_finite(parts[0], line_n)?);
+        tables[1].push(parse_finite(parts[1], line_n)?);
+        tables[2].push(parse_finite(parts[2], line_n)?);
     }
 
-    // Ensure we got the expected amount of data.
-    if length_1d > 0 || length_3d > 0 {
-        return Err(super::ReadError::FormatErr);
+    // Check for invalid header, in case there was no data at all.
+    if length_1d == 0 && size_3d == 0 {
+        return Err(ReadError::format(
+            line_n,
+            "LUT_1D_SIZE",
+            FormatErrorKind::MissingKeyword,
+        ));
     }
 
-    // Ensure all encountered values were valid (not inf or NaN).
-    if !tables_1d.iter().flatten().all(|n| n.is_finite())
-        || !tables_3d.iter().flatten().all(|n| n.is_finite())
-        || !range_1d.0.is_finite()
-        || !range_1d.1.is_finite()
-        || !range_3d.0.is_finite()
-        || !range_3d.1.is_finite()
-    {
-        // Non-finite values in the file.
-        return Err(super::ReadError::FormatErr);
+    // Ensure we got the expected amount of data.
+    let found = tables_1d[0].len() + tables_3d[0].len() + extra_entries;
+    if extra_entries > 0 || tables_1d[0].len() < length_1d || tables_3d[0].len() < length_3d {
+        let (line, keyword) = if extra_entries > 0 {
+            (extra_line, "")
+        } else if tables_1d[0].len() < length_1d {
+            (length_1d_line, "LUT_1D_SIZE")
+        } else {
+            (size_3d_line, "LUT_3D_SIZE")
+        };
+        return Err(ReadError::format(
+            line,
+            keyword,
+            FormatErrorKind::SizeMismatch {
+                expected: length_1d.saturating_add(length_3d),
+                found,
+            },
+        ));
     }
 
     // Build the LUT structs.
     let lut_1d = if !tables_1d[0].is_empty() {
         let [table_r, table_g, table_b] = tables_1d;
-        Some(Lut1D {
+        let lut = Lut1D {
             ranges: vec![range_1d],
             tables: vec![table_r, table_g, table_b],
-        })
+        };
+        lut.validate()
+            .map_err(|e| ReadError::invalid_lut(length_1d_line, "LUT_1D_SIZE", e))?;
+        Some(lut)
     } else {
         None
     };
     let lut_3d = if !tables_3d[0].is_empty() {
         let [table_r, table_g, table_b] = tables_3d;
-        Some(Lut3D {
+        let lut = Lut3D {
             range: [range_3d, range_3d, range_3d],
             resolution: [size_3d, size_3d, size_3d],
             tables: vec![table_r, table_g, table_b],
-        })
+        };
+        lut.validate()
+            .map_err(|e| ReadError::invalid_lut(size_3d_line, "LUT_3D_SIZE", e))?;
+        Some(lut)
     } else {
         None
     };
 
-    Ok((lut_1d, lut_3d))
+    Ok((lut_1d, lut_3d, metadata))
+}
+
+//-------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_round_trip() {
+        let data = "# Generated by a tool.\n\
+                    TITLE \"Show LUT\"\n\
+                    LUT_1D_SIZE 2\n\
+                    #Another comment.\n\
+                    0 0 0\n\
+                    1 1 1\n";
+        let (lut_1d, _, metadata) = read(data.as_bytes()).unwrap();
+        let lut_1d = lut_1d.unwrap();
+
+        assert_eq!(metadata.title.as_deref(), Some("Show LUT"));
+        assert_eq!(
+            metadata.comments,
+            vec![
+                "Generated by a tool.".to_string(),
+                "Another comment.".into()
+            ]
+        );
+
+        let mut data2 = Vec::new();
+        write(
+            &mut data2,
+            &metadata,
+            Some((
+                lut_1d.ranges[0].0,
+                lut_1d.ranges[0].1,
+                [&lut_1d.tables[0], &lut_1d.tables[1], &lut_1d.tables[2]],
+            )),
+            None,
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        let (_, _, metadata2) = read(&data2[..]).unwrap();
+
+        assert_eq!(metadata, metadata2);
+    }
+
+    #[test]
+    fn read_huge_size() {
+        // Must be an error, not an overflow panic, in both .cube readers.
+        let data = "LUT_3D_SIZE 4294967296\n0 0 0\n";
+        let is_invalid_size = |result: Result<(), ReadError>| {
+            matches!(
+                result,
+                Err(ReadError::FormatErr {
+                    line: 1,
+                    kind: FormatErrorKind::InvalidValue,
+                    ..
+                })
+            )
+        };
+        assert!(is_invalid_size(read(data.as_bytes()).map(|_| ())));
+        assert!(is_invalid_size(
+            super::super::cube_iridas::read_3d(data.as_bytes()).map(|_| ())
+        ));
+    }
 }
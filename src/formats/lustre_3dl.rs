@@ -0,0 +1,388 @@
+//! Autodesk/Lustre .3dl LUT format.
+//!
+//! This format contains a 3D LUT with integer-coded output values.  The
+//! first line of data is a "shaper" line that lists the integer-coded
+//! input value of each lattice point along an axis, which determines
+//! both the mesh resolution and the input bit depth.
+//!
+//! The LUT data itself is ordered with blue changing fastest, which is
+//! the opposite of the `Lut3D` type.  This is handled automatically by
+//! the functions in this module.
+
+use std::io::{BufRead, Write};
+
+use super::{expect_values, filter_non_finite, parse, FormatErrorKind, ReadError};
+use crate::lut::{validate_3d, Lut3D};
+
+/// The largest integer-coded value supported, which is the 16-bit maximum.
+const MAX_VALUE: u32 = 65535;
+
+/// The input bit depth used for the shaper line when writing.
+const WRITE_INPUT_BIT_DEPTH: u32 = 10;
+
+/// Writes a .3dl file.
+///
+/// The tables should have a length of `resolution * resolution * resolution`,
+/// and their indices should be ordered the same as the `Lut3D` type.
+/// The input range is always `[0.0, 1.0]`.
+///
+/// Output values are scaled to `output_bit_depth`, and clamped and
+/// rounded to the resulting integer range.  The output bit depth is
+/// recorded in a Lustre-style `Mesh` header, so that it doesn't need to
+/// be inferred when reading.  The shaper line is always written with an
+/// input bit depth of 10.
+///
+/// Invalid LUT data, a `resolution` too large for the shaper line to
+/// be strictly increasing at that bit depth (above 1024), or an
+/// `output_bit_depth` that isn't between 1 and 16, is reported as an
+/// error of kind `InvalidInput`.
+pub fn write<W: Write>(
+    mut writer: W,
+    resolution: usize,
+    tables: [&[f32]; 3],
+    output_bit_depth: u32,
+) -> std::io::Result<()> {
+    if resolution > 1 << WRITE_INPUT_BIT_DEPTH {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "resolution is too large for a 10-bit shaper line",
+        ));
+    }
+    validate_3d(&[(0.0, 1.0); 3], [resolution; 3], &tables)?;
+    if output_bit_depth == 0 || output_bit_depth > 16 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "output bit depth must be between 1 and 16",
+        ));
+    }
+
+    // Header.  The first `Mesh` value is the mesh size as a power of
+    // two, which for Lustre should be exactly `resolution - 1`, so it's
+    // rounded up for other resolutions.
+    let mesh_size = usize::BITS - (resolution - 2).leading_zeros();
+    writer.write_all(format!("3DMESH\nMesh {} {}\n", mesh_size, output_bit_depth).as_bytes())?;
+
+    // Shaper line.
+    let in_max = ((1u32 << WRITE_INPUT_BIT_DEPTH) - 1) as f64;
+    for i in 0..resolution {
+        let n = (i as f64 * in_max / (resolution - 1) as f64).round() as u32;
+        if i > 0 {
+            writer.write_all(b" ")?;
+        }
+        writer.write_all(format!("{}", n).as_bytes())?;
+    }
+    writer.write_all(b"\n")?;
+
+    // LUT data.
+    let out_max = ((1u32 << output_bit_depth) - 1) as f32;
+    let encode = |n: f32| (filter_non_finite(n) * out_max).round().clamp(0.0, out_max) as u32;
+    for ri in 0..resolution {
+        for gi in 0..resolution {
+            for bi in 0..resolution {
+                let i = ri + (gi * resolution) + (bi * resolution * resolution);
+                writer.write_all(
+                    format!(
+                        "{} {} {}\n",
+                        encode(tables[0][i]),
+                        encode(tables[1][i]),
+                        encode(tables[2][i]),
+                    )
+                    .as_bytes(),
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a .3dl file.
+///
+/// The input bit depth is inferred from the largest value in the shaper
+/// line, and the output bit depth is inferred from the largest output
+/// value in the file (unless specified by a Lustre-style `Mesh` header).
+/// In both cases, the smallest of 8, 10, 12, 14, or 16 bits that can
+/// represent the largest value is chosen.  The shaper line must be
+/// evenly spaced, since the returned LUT can't represent anything else.
+///
+/// The returned LUT has normalized input and output values.
+pub fn read<R: BufRead>(reader: R) -> Result<Lut3D, ReadError> {
+    let mut out_bit_depth = None;
+    let mut shaper: Vec<u32> = Vec::new();
+    let mut shaper_line = 0;
+    let mut tables = [Vec::new(), Vec::new(), Vec::new()];
+    let mut line_n = 0;
+
+    // Parses an integer-coded value, ensuring it fits in 16 bits.
+    let parse_value = |token: &str, line_n: usize| -> Result<u32, ReadError> {
+        let n = parse::<u32>(token, line_n)?;
+        if n <= MAX_VALUE {
+            Ok(n)
+        } else {
+            Err(ReadError::format(
+                line_n,
+                token,
+                FormatErrorKind::InvalidValue,
+            ))
+        }
+    };
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        line_n = i + 1;
+        let parts: Vec<_> = line.split_whitespace().collect();
+
+        if parts.is_empty() || parts[0].starts_with('#') || parts == ["3DMESH"] {
+            continue;
+        } else if parts[0] == "Mesh" {
+            expect_values(&parts, 2, line_n)?;
+            let depth = parse::<u32>(parts[2], line_n)?;
+            if depth == 0 || depth > 16 {
+                return Err(ReadError::format(
+                    line_n,
+                    parts[2],
+                    FormatErrorKind::InvalidValue,
+                ));
+            }
+            out_bit_depth = Some(depth);
+            continue;
+        } else if (parts[0] == "LUT8" && parts.len() == 1)
+            || (parts[0] == "gamma" && parts.len() == 2)
+        {
+            // Trailing Lustre metadata that we don't use.
+            continue;
+        } else if parts[0].parse::<u32>().is_err() {
+            // Line didn't match any acceptable pattern.
+            return Err(ReadError::format(
+                line_n,
+                parts[0],
+                FormatErrorKind::BadKeyword,
+            ));
+        } else if shaper.is_empty() {
+            for part in parts.iter() {
+                let n = parse_value(part, line_n)?;
+                if shaper.last().map(|last| n <= *last).unwrap_or(false) {
+                    // Must be strictly increasing.
+                    return Err(ReadError::format(
+                        line_n,
+                        part,
+                        FormatErrorKind::InvalidValue,
+                    ));
+                }
+                shaper.push(n);
+            }
+            if shaper.len() < 2 {
+                return Err(ReadError::format(
+                    line_n,
+                    line.trim(),
+                    FormatErrorKind::InvalidValue,
+                ));
+            }
+            // Must be evenly spaced, give or take integer rounding.
+            let step = (*shaper.last().unwrap() - shaper[0]) as f64 / (shaper.len() - 1) as f64;
+            for (j, (n, part)) in shaper.iter().zip(parts.iter()).enumerate() {
+                let expected = shaper[0] as f64 + (step * j as f64);
+                if (*n as f64 - expected).abs() > 1.0 {
+                    return Err(ReadError::format(
+                        line_n,
+                        part,
+                        FormatErrorKind::InvalidValue,
+                    ));
+                }
+            }
+            shaper_line = line_n;
+            continue;
+        } else if parts.len() == 3 {
+            tables[0].push(parse_value(parts[0], line_n)?);
+            tables[1].push(parse_value(parts[1], line_n)?);
+            tables[2].push(parse_value(parts[2], line_n)?);
+            continue;
+        } else {
+            return Err(ReadError::format(
+                line_n,
+                line.trim(),
+                FormatErrorKind::WrongValueCount {
+                    expected: 3,
+                    found: parts.len(),
+                },
+            ));
+        }
+    }
+
+    // Validate the data size.
+    let res = shaper.len();
+    if res == 0 {
+        return Err(ReadError::format(
+            line_n,
+            "",
+            FormatErrorKind::UnexpectedEnd,
+        ));
+    } else if tables[0].len() != (res * res * res) {
+        return Err(ReadError::format(
+            shaper_line,
+            "",
+            FormatErrorKind::SizeMismatch {
+                expected: res * res * res,
+                found: tables[0].len(),
+            },
+        ));
+    }
+
+    // Determine bit depths.
+    let in_max = bit_depth_max(infer_bit_depth(*shaper.last().unwrap()));
+    let out_max = bit_depth_max(
+        out_bit_depth
+            .unwrap_or_else(|| infer_bit_depth(tables.iter().flatten().copied().max().unwrap())),
+    );
+
+    // Reorder to `Lut3D` ordering and normalize.
+    let len = res * res * res;
+    let mut out_tables = vec![vec![0.0f32; len]; 3];
+    for file_i in 0..len {
+        let bi = file_i % res;
+        let gi = (file_i / res) % res;
+        let ri = file_i / (res * res);
+        let i = ri + (gi * res) + (bi * res * res);
+        for (out_table, table) in out_tables.iter_mut().zip(tables.iter()) {
+            out_table[i] = table[file_i] as f32 / out_max;
+        }
+    }
+
+    let range = (
+        shaper[0] as f32 / in_max,
+        *shaper.last().unwrap() as f32 / in_max,
+    );
+    Ok(Lut3D {
+        range: [range; 3],
+        resolution: [res, res, res],
+        tables: out_tables,
+    })
+}
+
+/// Returns the smallest standard bit depth that can represent `max_value`.
+///
+/// `max_value` must not exceed `MAX_VALUE`.
+fn infer_bit_depth(max_value: u32) -> u32 {
+    [8, 10, 12, 14, 16]
+        .iter()
+        .copied()
+        .find(|depth| max_value < (1 << depth))
+        .unwrap()
+}
+
+fn bit_depth_max(bit_depth: u32) -> f32 {
+    ((1u32 << bit_depth) - 1) as f32
+}
+
+//-------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_01() {
+        let data = "# A comment.\n\
+                    0 1023\n\
+                    0 0 0\n\
+                    0 0 4095\n\
+                    0 4095 0\n\
+                    0 4095 4095\n\
+                    4095 0 0\n\
+                    4095 0 4095\n\
+                    4095 4095 0\n\
+                    4095 4095 4095\n";
+        let lut = read(data.as_bytes()).unwrap();
+
+        assert_eq!(lut.resolution, [2, 2, 2]);
+        assert_eq!(lut.range, [(0.0, 1.0); 3]);
+        // Should be an identity LUT after reordering.
+        for (i, channel) in lut.tables.iter().enumerate() {
+            for (j, v) in channel.iter().enumerate() {
+                let expected = (j >> i) & 1;
+                assert_eq!(*v, expected as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let lut = Lut3D::from_fn([5, 5, 5], [0.0; 3], [1.0; 3], |(r, g, b)| {
+            (r * 0.5, g * g, b.sqrt())
+        });
+
+        let mut data = Vec::new();
+        write(
+            &mut data,
+            5,
+            [&lut.tables[0], &lut.tables[1], &lut.tables[2]],
+            16,
+        )
+        .unwrap();
+        let lut2 = read(&data[..]).unwrap();
+
+        assert_eq!(lut2.resolution, lut.resolution);
+        assert_eq!(lut2.range, lut.range);
+        for (a, b) in lut
+            .tables
+            .iter()
+            .flatten()
+            .zip(lut2.tables.iter().flatten())
+        {
+            assert!((a - b).abs() <= 0.5 / 65535.0);
+        }
+    }
+
+    #[test]
+    fn write_read_round_trip_dark() {
+        // Output values this low would be misread without the `Mesh`
+        // header, since the bit depth would be inferred as too small.
+        let lut = Lut3D::from_fn([5, 5, 5], [0.0; 3], [1.0; 3], |(r, g, b)| {
+            (r * 0.2, g * g * 0.1, b * 0.05)
+        });
+
+        for depth in [9, 10, 12] {
+            let mut data = Vec::new();
+            write(
+                &mut data,
+                5,
+                [&lut.tables[0], &lut.tables[1], &lut.tables[2]],
+                depth,
+            )
+            .unwrap();
+            let lut2 = read(&data[..]).unwrap();
+
+            let tolerance = 0.5 / ((1 << depth) - 1) as f32;
+            for (a, b) in lut
+                .tables
+                .iter()
+                .flatten()
+                .zip(lut2.tables.iter().flatten())
+            {
+                assert!((a - b).abs() <= tolerance);
+            }
+        }
+    }
+
+    #[test]
+    fn write_too_large() {
+        let err = write(Vec::new(), 1025, [&[], &[], &[]], 16).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn read_uneven_shaper() {
+        let lattice = "0 0 0\n".repeat(27);
+        let lut = read(format!("0 512 1023\n{}", lattice).as_bytes()).unwrap();
+        assert_eq!(lut.range, [(0.0, 1.0); 3]);
+
+        assert!(matches!(
+            read(format!("0 100 1023\n{}", lattice).as_bytes()),
+            Err(ReadError::FormatErr {
+                line: 1,
+                kind: FormatErrorKind::InvalidValue,
+                ..
+            })
+        ));
+    }
+}
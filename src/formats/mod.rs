@@ -1,8 +1,19 @@
 //! Functions for reading and writing various file formats.
 
+pub mod cinespace;
+pub mod clf;
 pub mod cube_iridas;
 pub mod cube_resolve;
+pub mod lustre_3dl;
 pub mod spi1d;
+pub mod spi3d;
+pub mod spimtx;
+
+use std::io::{BufRead, Read};
+use std::path::Path;
+
+use crate::lut::{Lut1D, Lut1DNonUniform, Lut3D, LutError};
+use crate::matrix::Matrix;
 
 fn filter_non_finite(n: f32) -> f32 {
     if n.is_finite() {
@@ -12,10 +23,248 @@ fn filter_non_finite(n: f32) -> f32 {
     }
 }
 
+/// Options for how text-based LUT writers format their output.
+///
+/// The default matches what the writers have always done: shortest
+/// round-trip numbers and Unix line endings.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    pub number_format: NumberFormat,
+    pub line_ending: LineEnding,
+}
+
+/// How numbers are written.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// The shortest representation that reads back as the exact same
+    /// value, e.g. `0.5` or `1e-7`.
+    #[default]
+    Shortest,
+    /// Fixed-point notation with the given number of decimal places,
+    /// e.g. `0.500000` for 6 places.
+    Fixed(usize),
+    /// Scientific notation with the given number of decimal places,
+    /// e.g. `5.000000e-1` for 6 places.
+    Scientific(usize),
+}
+
+/// The line ending to write.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`
+    #[default]
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+impl WriteOptions {
+    /// Writes a number, replacing non-finite values with zero.
+    fn write_number<W: std::io::Write>(&self, mut writer: W, n: f32) -> std::io::Result<()> {
+        let n = filter_non_finite(n);
+        match self.number_format {
+            NumberFormat::Shortest => write!(writer, "{}", n),
+            NumberFormat::Fixed(places) => write!(writer, "{:.*}", places, n),
+            NumberFormat::Scientific(places) => write!(writer, "{:.*e}", places, n),
+        }
+    }
+
+    /// Writes numbers separated by spaces, and ends the line.
+    fn write_values<W: std::io::Write>(
+        &self,
+        mut writer: W,
+        values: &[f32],
+    ) -> std::io::Result<()> {
+        for (i, n) in values.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b" ")?;
+            }
+            self.write_number(&mut writer, *n)?;
+        }
+        self.end_line(writer)
+    }
+
+    fn end_line<W: std::io::Write>(&self, mut writer: W) -> std::io::Result<()> {
+        match self.line_ending {
+            LineEnding::Lf => writer.write_all(b"\n"),
+            LineEnding::CrLf => writer.write_all(b"\r\n"),
+        }
+    }
+}
+
+/// Descriptive metadata of a .cube file.
+///
+/// Both the IRIDAS and Resolve variants of the format support a title
+/// and `#` comment lines, which typically carry provenance information.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CubeMetadata {
+    /// The contents of the `TITLE` line, if any.
+    pub title: Option<String>,
+    /// The comment lines, in file order, without their leading `#`
+    /// and the single space that conventionally follows it.
+    pub comments: Vec<String>,
+}
+
+/// Writes the title and comments of a .cube file.
+fn write_cube_metadata<W: std::io::Write>(
+    mut writer: W,
+    metadata: &CubeMetadata,
+    options: &WriteOptions,
+) -> std::io::Result<()> {
+    if let Some(title) = &metadata.title {
+        // The format has no way to escape quotes.
+        if title.contains(['"', '\n']) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a .cube title can't contain double quotes or line breaks",
+            ));
+        }
+        write!(writer, "TITLE \"{}\"", title)?;
+        options.end_line(&mut writer)?;
+    }
+    for comment in metadata.comments.iter() {
+        for line in comment.lines() {
+            write!(writer, "# {}", line)?;
+            options.end_line(&mut writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a .cube `TITLE` line, returning the title.
+fn parse_cube_title(line: &str, line_n: usize) -> Result<String, ReadError> {
+    let name_parts: Vec<_> = line.trim().split('"').collect();
+    if name_parts.len() != 3 || !name_parts[2].is_empty() {
+        return Err(ReadError::format(
+            line_n,
+            line.trim(),
+            FormatErrorKind::InvalidValue,
+        ));
+    }
+    Ok(name_parts[1].into())
+}
+
+/// Returns the text of a .cube comment line, if it is one.
+fn parse_cube_comment(line: &str) -> Option<String> {
+    let comment = line.trim_start().strip_prefix('#')?;
+    Some(
+        comment
+            .strip_prefix(' ')
+            .unwrap_or(comment)
+            .trim_end()
+            .into(),
+    )
+}
+
+/// An error that occurred while reading a file.
 #[derive(Debug)]
 pub enum ReadError {
     IoErr(std::io::Error),
-    FormatErr,
+
+    /// The file contents don't conform to the format.
+    ///
+    /// - `line`: the (1-based) line number where the error was found.
+    ///   For errors that are only detected at the end of the file, this
+    ///   is the line of the relevant header keyword if there is one, or
+    ///   the last line of the file otherwise.
+    /// - `token`: the offending token, or an empty string if there isn't
+    ///   a specific one.
+    /// - `kind`: what's wrong.
+    FormatErr {
+        line: usize,
+        token: String,
+        kind: FormatErrorKind,
+    },
+}
+
+/// The reason for a `ReadError::FormatErr`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FormatErrorKind {
+    /// An unrecognized or misplaced keyword, or an otherwise
+    /// unrecognizable line.
+    BadKeyword,
+    /// A required keyword is missing.  The token is the keyword.
+    MissingKeyword,
+    /// A value that couldn't be parsed as a number.
+    BadNumber,
+    /// A NaN or infinite value.
+    NonFiniteValue,
+    /// A line or element with the wrong number of values.
+    WrongValueCount { expected: usize, found: usize },
+    /// The amount of LUT data doesn't match the size specified in the file.
+    SizeMismatch { expected: usize, found: usize },
+    /// A value that parsed correctly, but isn't valid in context (e.g. an
+    /// out-of-range index or a LUT size that's too small).
+    InvalidValue,
+    /// The file ended before all expected data was read.
+    UnexpectedEnd,
+    /// The file's format couldn't be determined.
+    UnknownFormat,
+    /// The file parsed correctly, but the LUT it describes is invalid
+    /// (e.g. a 3D LUT with a resolution of 1).
+    InvalidLut(LutError),
+}
+
+impl ReadError {
+    fn format(line: usize, token: &str, kind: FormatErrorKind) -> ReadError {
+        ReadError::FormatErr {
+            line,
+            token: token.into(),
+            kind,
+        }
+    }
+
+    fn invalid_lut(line: usize, token: &str, error: LutError) -> ReadError {
+        ReadError::format(line, token, FormatErrorKind::InvalidLut(error))
+    }
+}
+
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::IoErr(error) => write!(f, "{}", error),
+            ReadError::FormatErr { line, token, kind } => {
+                write!(f, "line {}: {}", line, kind)?;
+                if !token.is_empty() {
+                    write!(f, " at \"{}\"", token)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for FormatErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatErrorKind::BadKeyword => write!(f, "unrecognized keyword or line"),
+            FormatErrorKind::MissingKeyword => write!(f, "missing required keyword"),
+            FormatErrorKind::BadNumber => write!(f, "invalid number"),
+            FormatErrorKind::NonFiniteValue => write!(f, "non-finite value"),
+            FormatErrorKind::WrongValueCount { expected, found } => {
+                write!(f, "expected {} values, found {}", expected, found)
+            }
+            FormatErrorKind::SizeMismatch { expected, found } => write!(
+                f,
+                "size mismatch, expected {} entries, found {}",
+                expected, found
+            ),
+            FormatErrorKind::InvalidValue => write!(f, "invalid value"),
+            FormatErrorKind::UnexpectedEnd => write!(f, "unexpected end of file"),
+            FormatErrorKind::UnknownFormat => write!(f, "unknown file format"),
+            FormatErrorKind::InvalidLut(error) => write!(f, "invalid LUT: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReadError::IoErr(error) => Some(error),
+            ReadError::FormatErr { .. } => None,
+        }
+    }
 }
 
 impl From<std::io::Error> for ReadError {
@@ -24,14 +273,590 @@ impl From<std::io::Error> for ReadError {
     }
 }
 
-impl From<std::num::ParseFloatError> for ReadError {
-    fn from(_error: std::num::ParseFloatError) -> ReadError {
-        ReadError::FormatErr
+/// Parses a token as a number, reporting errors at the given line.
+fn parse<T: std::str::FromStr>(token: &str, line: usize) -> Result<T, ReadError> {
+    token
+        .parse::<T>()
+        .map_err(|_| ReadError::format(line, token, FormatErrorKind::BadNumber))
+}
+
+/// Like `parse()`, but also reports an error for NaN and infinite values.
+fn parse_finite<T: std::str::FromStr + Into<f64> + Copy>(
+    token: &str,
+    line: usize,
+) -> Result<T, ReadError> {
+    let n = parse::<T>(token, line)?;
+    if n.into().is_finite() {
+        Ok(n)
+    } else {
+        Err(ReadError::format(
+            line,
+            token,
+            FormatErrorKind::NonFiniteValue,
+        ))
+    }
+}
+
+/// Checks that a keyword line has the expected number of values after
+/// the keyword.
+fn expect_values(parts: &[&str], expected: usize, line: usize) -> Result<(), ReadError> {
+    if parts.len() == expected + 1 {
+        Ok(())
+    } else {
+        Err(ReadError::format(
+            line,
+            parts[0],
+            FormatErrorKind::WrongValueCount {
+                expected,
+                found: parts.len() - 1,
+            },
+        ))
+    }
+}
+
+//-------------------------------------------------------------
+// Fast text I/O.
+//
+// The text formats can have millions of values in them (e.g. a 129^3
+// .cube file), so the hot reading loops avoid allocating per line, and
+// the writers format directly into a buffer.
+
+/// The buffer size used by writers that write a lot of small values.
+const WRITE_BUFFER_SIZE: usize = 1 << 16;
+
+/// Reads lines into a single reused buffer, tracking line numbers.
+struct LineReader<R: BufRead> {
+    reader: R,
+    buffer: String,
+    line_n: usize,
+}
+
+impl<R: BufRead> LineReader<R> {
+    fn new(reader: R) -> LineReader<R> {
+        LineReader {
+            reader,
+            buffer: String::new(),
+            line_n: 0,
+        }
+    }
+
+    /// Reads the next line, without its line ending.
+    ///
+    /// Returns the (1-based) line number along with the line, or `None`
+    /// at the end of the input.
+    fn next_line(&mut self) -> Result<Option<(usize, &str)>, ReadError> {
+        self.buffer.clear();
+        if self.reader.read_line(&mut self.buffer)? == 0 {
+            return Ok(None);
+        }
+        self.line_n += 1;
+        Ok(Some((
+            self.line_n,
+            self.buffer.trim_end_matches(['\n', '\r']),
+        )))
+    }
+}
+
+/// The maximum number of tokens per line that `Tokens` keeps.
+const MAX_TOKENS: usize = 8;
+
+/// The whitespace-separated tokens of a line, split without allocating.
+///
+/// Dereferences to a slice of the tokens.  Only the first `MAX_TOKENS`
+/// tokens of a line are kept, which is more than any valid line of the
+/// formats that use this has.
+struct Tokens<'a> {
+    tokens: [&'a str; MAX_TOKENS],
+    len: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(line: &'a str) -> Tokens<'a> {
+        let mut tokens = [""; MAX_TOKENS];
+        let mut len = 0;
+        for (slot, token) in tokens.iter_mut().zip(line.split_ascii_whitespace()) {
+            *slot = token;
+            len += 1;
+        }
+        Tokens { tokens, len }
+    }
+}
+
+impl<'a> std::ops::Deref for Tokens<'a> {
+    type Target = [&'a str];
+
+    fn deref(&self) -> &[&'a str] {
+        &self.tokens[..self.len]
+    }
+}
+
+/// Returns whether a token looks like a number.
+///
+/// This is a cheap check for the common case, so that LUT data lines can
+/// be distinguished from keyword lines without parsing every value twice.
+fn is_numeric_token(token: &str) -> bool {
+    match token.as_bytes().first() {
+        Some(b'0'..=b'9' | b'-' | b'+' | b'.') => true,
+        // Handles e.g. "nan" and "inf".
+        _ => token.parse::<f32>().is_ok(),
     }
 }
 
-impl From<std::num::ParseIntError> for ReadError {
-    fn from(_error: std::num::ParseIntError) -> ReadError {
-        ReadError::FormatErr
+//-------------------------------------------------------------
+// Format auto-detection.
+
+/// The file formats supported by `read_any()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Cinespace,
+    Clf,
+    CubeIridas1D,
+    CubeIridas3D,
+    CubeResolve,
+    Lustre3DL,
+    Spi1D,
+    Spi3D,
+    SpiMtx,
+}
+
+/// The contents of a file read by `read_any()`.
+#[derive(Debug, Clone)]
+pub enum AnyLut {
+    Lut1D(Lut1D),
+    Lut3D(Lut3D),
+    /// A 1D LUT that is applied before a 3D LUT.
+    Lut1DAnd3D(Lut1D, Lut3D),
+    /// Per-channel non-uniform pre-LUT shapers that are applied before a
+    /// 3D LUT.
+    Shaped3D(Lut1DNonUniform, Lut3D),
+    /// A matrix and an offset that is added after the matrix.
+    Matrix(Matrix, [f64; 3]),
+    ProcessList(clf::ProcessList),
+}
+
+/// Reads a LUT file of any supported format.
+///
+/// The format is determined from both the file extension and the file
+/// contents.  See `detect_format()` for details.
+pub fn read_any<P: AsRef<Path>>(path: P) -> Result<(Format, AnyLut), ReadError> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let file = std::fs::File::open(path)?;
+    read_any_from(std::io::BufReader::new(file), extension)
+}
+
+/// Like `read_any()`, but reads from a reader.
+///
+/// `extension` is an optional file extension (without the leading
+/// period) to help determine the format.
+pub fn read_any_from<R: Read>(
+    mut reader: R,
+    extension: Option<&str>,
+) -> Result<(Format, AnyLut), ReadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let format = detect_format(extension, &data)
+        .ok_or_else(|| ReadError::format(1, "", FormatErrorKind::UnknownFormat))?;
+    let lut = match format {
+        Format::Cinespace => {
+            let (shapers, lut_3d) = cinespace::read(&data[..])?;
+            AnyLut::Shaped3D(shapers, lut_3d)
+        }
+        Format::Clf => AnyLut::ProcessList(clf::read(&data[..])?),
+        Format::CubeIridas1D => AnyLut::Lut1D(cube_iridas::read_1d(&data[..])?.0),
+        Format::CubeIridas3D => AnyLut::Lut3D(cube_iridas::read_3d(&data[..])?.0),
+        Format::CubeResolve => match cube_resolve::read(&data[..])? {
+            (Some(lut_1d), Some(lut_3d), _) => AnyLut::Lut1DAnd3D(lut_1d, lut_3d),
+            (Some(lut_1d), None, _) => AnyLut::Lut1D(lut_1d),
+            (None, Some(lut_3d), _) => AnyLut::Lut3D(lut_3d),
+            // `cube_resolve::read()` always returns at least one LUT.
+            (None, None, _) => unreachable!(),
+        },
+        Format::Lustre3DL => AnyLut::Lut3D(lustre_3dl::read(&data[..])?),
+        Format::Spi1D => AnyLut::Lut1D(spi1d::read(&data[..])?),
+        Format::Spi3D => AnyLut::Lut3D(spi3d::read(&data[..])?),
+        Format::SpiMtx => {
+            let (matrix, offset) = spimtx::read(&data[..])?;
+            AnyLut::Matrix(matrix, offset)
+        }
+    };
+
+    Ok((format, lut))
+}
+
+/// Determines the format of a LUT file.
+///
+/// - `extension`: the file extension (without the leading period), if
+///   known.  It is compared case-insensitively.
+/// - `data`: the contents of the file.
+///
+/// Formats with a distinctive header are detected from the contents
+/// alone.  The .cube variants are distinguished by their keywords: files
+/// with `DOMAIN_MIN`/`DOMAIN_MAX` are treated as IRIDAS, and all other
+/// files are treated as Resolve (which is a superset of IRIDAS otherwise).
+/// The extension is used to resolve the remaining ambiguity between the
+/// headerless .3dl and .spimtx formats.
+///
+/// Returns `None` if the format can't be determined.
+pub fn detect_format(extension: Option<&str>, data: &[u8]) -> Option<Format> {
+    let extension = extension.map(|ext| ext.to_ascii_lowercase());
+    let text = String::from_utf8_lossy(data);
+    let text = text.trim_start_matches('\u{feff}');
+
+    // Significant lines, with comments and blank lines removed.
+    let mut lines = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    let first_line = lines.clone().next()?;
+    let first_token = first_line.split_whitespace().next()?;
+    let is_number = |token: &str| token.parse::<f64>().is_ok();
+
+    // Formats with distinctive headers.
+    if first_line.starts_with('<') {
+        return Some(Format::Clf);
+    }
+    match first_token {
+        "CSPLUTV100" => return Some(Format::Cinespace),
+        "SPILUT" => return Some(Format::Spi3D),
+        "Version" => return Some(Format::Spi1D),
+        "3DMESH" | "Mesh" => return Some(Format::Lustre3DL),
+        _ => {}
+    }
+
+    // .cube files.
+    if !is_number(first_token) || extension.as_deref() == Some("cube") {
+        let mut has_domain = false;
+        let mut has_1d = false;
+        let mut has_3d = false;
+        for line in lines.by_ref() {
+            let keyword = line.split_whitespace().next().unwrap();
+            match keyword {
+                "DOMAIN_MIN" | "DOMAIN_MAX" => has_domain = true,
+                "LUT_1D_SIZE" => has_1d = true,
+                "LUT_3D_SIZE" => has_3d = true,
+                _ if is_number(keyword) => break,
+                _ => {}
+            }
+        }
+
+        return match (has_domain, has_1d, has_3d) {
+            (true, true, false) => Some(Format::CubeIridas1D),
+            (true, false, true) => Some(Format::CubeIridas3D),
+            (false, true, _) | (false, _, true) => Some(Format::CubeResolve),
+            _ => None,
+        };
+    }
+
+    // Headerless numeric formats.
+    match extension.as_deref() {
+        Some("3dl") => Some(Format::Lustre3DL),
+        Some("spimtx") => Some(Format::SpiMtx),
+        _ => {
+            // A .spimtx file is twelve numbers, conventionally in three rows
+            // of four.  A .3dl file's first line is its shaper line, which
+            // is typically much longer than four numbers.
+            let rows: Vec<usize> = lines.map(|l| l.split_whitespace().count()).collect();
+            if rows == [4, 4, 4] {
+                Some(Format::SpiMtx)
+            } else if rows.len() > 1 && rows[1..].iter().all(|n| *n == 3) {
+                Some(Format::Lustre3DL)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+//-------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_format_01() {
+        let cases: &[(Option<&str>, &str, Option<Format>)] = &[
+            (
+                None,
+                "<?xml version=\"1.0\"?>\n<ProcessList>",
+                Some(Format::Clf),
+            ),
+            (None, "CSPLUTV100\n3D\n", Some(Format::Cinespace)),
+            (None, "SPILUT 1.0\n3 3\n", Some(Format::Spi3D)),
+            (None, "Version 1\nFrom 0.0 1.0\n", Some(Format::Spi1D)),
+            (None, "3DMESH\nMesh 4 12\n", Some(Format::Lustre3DL)),
+            (
+                Some("cube"),
+                "TITLE \"a\"\nDOMAIN_MIN 0 0 0\nLUT_1D_SIZE 2\n0 0 0\n1 1 1\n",
+                Some(Format::CubeIridas1D),
+            ),
+            (
+                None,
+                "# Comment.\nDOMAIN_MAX 1 1 1\nLUT_3D_SIZE 2\n0 0 0\n",
+                Some(Format::CubeIridas3D),
+            ),
+            (
+                Some("CUBE"),
+                "LUT_1D_SIZE 2\nLUT_3D_SIZE 2\n0 0 0\n",
+                Some(Format::CubeResolve),
+            ),
+            (None, "1 0 0 0\n0 1 0 0\n0 0 1 0\n", Some(Format::SpiMtx)),
+            (
+                Some("spimtx"),
+                "1 0 0 0 0 1 0 0 0 0 1 0\n",
+                Some(Format::SpiMtx),
+            ),
+            (
+                None,
+                "0 1023\n0 0 0\n4095 4095 4095\n",
+                Some(Format::Lustre3DL),
+            ),
+            (None, "Nonsense.\n", None),
+            (None, "", None),
+        ];
+
+        for (extension, data, format) in cases.iter() {
+            assert_eq!(detect_format(*extension, data.as_bytes()), *format);
+        }
+    }
+
+    #[test]
+    fn read_any_from_01() {
+        let data = "LUT_1D_SIZE 2\nLUT_3D_SIZE 2\n\
+                    0 0 0\n1 1 1\n\
+                    0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+        match read_any_from(data.as_bytes(), Some("cube")).unwrap() {
+            (Format::CubeResolve, AnyLut::Lut1DAnd3D(lut_1d, lut_3d)) => {
+                assert_eq!(lut_1d.tables[0], vec![0.0, 1.0]);
+                assert_eq!(lut_3d.resolution, [2, 2, 2]);
+            }
+            _ => panic!("Wrong format or LUT type."),
+        }
+    }
+
+    #[test]
+    fn read_invalid_lut() {
+        let kind = |result: Result<(), ReadError>| match result {
+            Err(ReadError::FormatErr { line, kind, .. }) => (line, kind),
+            _ => panic!("Expected a format error."),
+        };
+
+        assert_eq!(
+            kind(cube_iridas::read_3d("LUT_3D_SIZE 0\n".as_bytes()).map(|_| ())),
+            (
+                1,
+                FormatErrorKind::InvalidLut(LutError::ResolutionTooLow {
+                    axis: 0,
+                    resolution: 0
+                })
+            )
+        );
+        assert_eq!(
+            kind(
+                cube_iridas::read_1d("DOMAIN_MIN 0 1 0\nLUT_1D_SIZE 2\n0 0 0\n1 1 1\n".as_bytes())
+                    .map(|_| ())
+            ),
+            (
+                2,
+                FormatErrorKind::InvalidLut(LutError::BadRange { index: 1 })
+            )
+        );
+        assert_eq!(
+            kind(cube_resolve::read("TITLE \"x\"\nLUT_3D_SIZE 1\n0 0 0\n".as_bytes()).map(|_| ())),
+            (
+                2,
+                FormatErrorKind::InvalidLut(LutError::ResolutionTooLow {
+                    axis: 0,
+                    resolution: 1
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn line_reader_and_tokens() {
+        let data = "a b\r\n\n  c\td  e \nlast";
+        let mut lines = LineReader::new(data.as_bytes());
+        let mut result = Vec::new();
+        while let Some((line_n, line)) = lines.next_line().unwrap() {
+            result.push((line_n, Tokens::new(line).join(",")));
+        }
+
+        assert_eq!(
+            result,
+            vec![
+                (1, "a,b".to_string()),
+                (2, "".into()),
+                (3, "c,d,e".into()),
+                (4, "last".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_options() {
+        let tables: [&[f32]; 3] = [&[0.0, 1.0], &[0.0, 0.5], &[1e-7, f32::NAN]];
+
+        let mut data = Vec::new();
+        cube_iridas::write_1d(
+            &mut data,
+            &CubeMetadata::default(),
+            [(0.0, 1.0); 3],
+            tables,
+            &WriteOptions {
+                number_format: NumberFormat::Fixed(3),
+                line_ending: LineEnding::CrLf,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(data.clone()).unwrap(),
+            "DOMAIN_MIN 0.000 0.000 0.000\r\n\
+             DOMAIN_MAX 1.000 1.000 1.000\r\n\
+             LUT_1D_SIZE 2\r\n\
+             0.000 0.000 0.000\r\n\
+             1.000 0.500 0.000\r\n"
+        );
+        assert!(cube_iridas::read_1d(&data[..]).is_ok());
+
+        let mut data = Vec::new();
+        spi1d::write(
+            &mut data,
+            0.0,
+            1.0,
+            &tables[2..],
+            &WriteOptions {
+                number_format: NumberFormat::Scientific(2),
+                ..WriteOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "Version 1\nFrom 0.00e0 1.00e0\nLength 2\nComponents 1\n{\n  1.00e-7\n  0.00e0\n}\n"
+        );
+    }
+
+    #[test]
+    fn read_error_position() {
+        let data = "LUT_3D_SIZE 2\n0 0 0\n1 0 0\n0 1 0\n1 1 nan\n";
+        match cube_resolve::read(data.as_bytes()) {
+            Err(ReadError::FormatErr { line, token, kind }) => {
+                assert_eq!(line, 5);
+                assert_eq!(token, "nan");
+                assert_eq!(kind, FormatErrorKind::NonFiniteValue);
+            }
+            _ => panic!("Expected a format error."),
+        }
+
+        let data = "LUT_3D_SIZE 2\n0 0 0\n1 0 0\n";
+        match cube_resolve::read(data.as_bytes()) {
+            Err(ReadError::FormatErr { line, kind, .. }) => {
+                assert_eq!(line, 1);
+                assert_eq!(
+                    kind,
+                    FormatErrorKind::SizeMismatch {
+                        expected: 8,
+                        found: 2
+                    }
+                );
+            }
+            _ => panic!("Expected a format error."),
+        }
+
+        let data = "<ProcessList id=\"a\">\n<Matrix>\n<Array dim=\"3 3\">\n1 0 0\n0 x 0\n0 0 1\n</Array>\n</Matrix>\n</ProcessList>\n";
+        match clf::read(data.as_bytes()) {
+            Err(ReadError::FormatErr { line, token, kind }) => {
+                assert_eq!(line, 5);
+                assert_eq!(token, "x");
+                assert_eq!(kind, FormatErrorKind::BadNumber);
+            }
+            _ => panic!("Expected a format error."),
+        }
+    }
+
+    #[test]
+    fn write_invalid_input() {
+        use crate::lut::LutError;
+        use std::io::ErrorKind;
+
+        // Returns the `LutError` wrapped in a writer's error.
+        fn lut_error(result: std::io::Result<()>) -> LutError {
+            let error = result.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            *error.into_inner().unwrap().downcast::<LutError>().unwrap()
+        }
+
+        let options = WriteOptions::default();
+        let short = [0.0f32, 0.5, 1.0];
+        let long = [0.0f32, 0.25, 0.5, 0.75, 1.0];
+        let cube = [0.0f32; 8];
+        let mut data = Vec::new();
+
+        assert_eq!(
+            lut_error(cube_iridas::write_1d(
+                &mut data,
+                &CubeMetadata::default(),
+                [(0.0, 1.0); 3],
+                [&short, &long, &short],
+                &options,
+            )),
+            LutError::TableLengthMismatch {
+                index: 1,
+                expected: 3,
+                found: 5
+            }
+        );
+        assert_eq!(
+            lut_error(cube_iridas::write_3d(
+                &mut data,
+                &CubeMetadata::default(),
+                [(0.0, 1.0), (1.0, 1.0), (0.0, 1.0)],
+                2,
+                [&cube, &cube, &cube],
+                &options,
+            )),
+            LutError::BadRange { index: 1 }
+        );
+        assert_eq!(
+            lut_error(spi3d::write(&mut data, [2, 2, 3], [&cube, &cube, &cube])),
+            LutError::TableLengthMismatch {
+                index: 0,
+                expected: 12,
+                found: 8
+            }
+        );
+        assert_eq!(
+            lut_error(spi1d::write(&mut data, 0.0, 1.0, &[], &options)),
+            LutError::NoTables
+        );
+
+        // Errors that aren't about the LUT data itself.
+        let metadata = CubeMetadata {
+            title: Some("A \"quoted\" title".into()),
+            comments: Vec::new(),
+        };
+        let result = cube_resolve::write(
+            &mut data,
+            &metadata,
+            Some((0.0, 1.0, [&short, &short, &short])),
+            None,
+            &options,
+        );
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        let result = cube_resolve::write(&mut data, &CubeMetadata::default(), None, None, &options);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        let mut matrix = crate::matrix::rgb_to_xyz_matrix(crate::chroma::REC709);
+        let result = spimtx::write(&mut data, matrix, [1.0e308, 0.0, 0.0]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        matrix[1][2] = f64::NAN;
+        let result = spimtx::write(&mut data, matrix, [0.0; 3]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        // Nothing should have been written.
+        assert!(data.is_empty());
     }
 }
@@ -1,86 +1,139 @@
 //! Sony Pictures Imageworks' 1D LUT format.
 
-use std::io::{BufRead, Write};
+use std::io::{BufRead, BufWriter, Write};
 
-use super::filter_non_finite;
-use crate::lut::Lut1D;
+use super::{
+    expect_values, parse, parse_finite, FormatErrorKind, LineReader, ReadError, Tokens,
+    WriteOptions, WRITE_BUFFER_SIZE,
+};
+use crate::lut::{validate_1d, validate_table_lengths, Lut1D, LutError};
 
 /// Writes an SPI 1D LUT file.
 ///
 /// Takes a variable number of tables between 1 and 3.  A 1 to
 /// 3-component table file will be written depending on the number of
-/// tables passed.
+/// tables passed.  The tables must all have the same length.  Invalid
+/// LUT data is reported as an error of kind `InvalidInput`.
+///
+/// `options` controls the number formatting and line endings.
 pub fn write<W: Write>(
-    mut writer: W,
+    writer: W,
     range_min: f32,
     range_max: f32,
     tables: &[&[f32]],
+    options: &WriteOptions,
 ) -> std::io::Result<()> {
-    assert!(tables.len() > 0 && tables.len() <= 3);
-    assert!(tables.iter().all(|t| t.len() == tables[0].len()));
+    validate_1d(&[(range_min, range_max)], tables)?;
+    if tables.len() > 3 {
+        return Err(LutError::WrongTableCount {
+            expected: 3,
+            found: tables.len(),
+        }
+        .into());
+    }
+    validate_table_lengths(tables, tables[0].len())?;
+
+    let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, writer);
 
-    writer.write_all(b"Version 1\n")?;
-    writer.write_all(
-        format!(
-            "From {} {}\n",
-            filter_non_finite(range_min),
-            filter_non_finite(range_max)
-        )
-        .as_bytes(),
-    )?;
-    writer.write_all(format!("Length {}\n", tables[0].len()).as_bytes())?;
-    writer.write_all(format!("Components {}\n", tables.len()).as_bytes())?;
-    writer.write_all(b"{\n")?;
+    writer.write_all(b"Version 1")?;
+    options.end_line(&mut writer)?;
+    writer.write_all(b"From ")?;
+    options.write_values(&mut writer, &[range_min, range_max])?;
+    write!(writer, "Length {}", tables[0].len())?;
+    options.end_line(&mut writer)?;
+    write!(writer, "Components {}", tables.len())?;
+    options.end_line(&mut writer)?;
+    writer.write_all(b"{")?;
+    options.end_line(&mut writer)?;
     for i in 0..tables[0].len() {
         writer.write_all(b" ")?;
         for t in tables.iter() {
-            writer.write_all(format!(" {}", filter_non_finite(t[i])).as_bytes())?;
+            writer.write_all(b" ")?;
+            options.write_number(&mut writer, t[i])?;
         }
-        writer.write_all(b"\n")?;
+        options.end_line(&mut writer)?;
     }
-    writer.write_all(b"}\n")?;
+    writer.write_all(b"}")?;
+    options.end_line(&mut writer)?;
 
-    Ok(())
+    writer.flush()
 }
 
 /// Reads an SPI 1D LUT file.
-pub fn read<R: BufRead>(reader: R) -> Result<Lut1D, super::ReadError> {
+pub fn read<R: BufRead>(reader: R) -> Result<Lut1D, ReadError> {
     // let mut name: Option<String> = None;
     let mut range_min = 0.0;
     let mut range_max = 1.0;
     let mut length = 0;
+    let mut length_line = 0;
     let mut components = 0;
     let mut tables = Vec::new();
     let mut reading_table = false;
 
-    for line in reader.lines() {
-        let line = line?;
-        let parts: Vec<_> = line.split_whitespace().collect();
+    let mut lines = LineReader::new(reader);
+    let mut line_n = 0;
+    while let Some((n, line)) = lines.next_line()? {
+        line_n = n;
+        let parts = Tokens::new(line);
 
         if parts.is_empty() {
             continue;
         }
 
         if !reading_table {
-            if parts[0] == "Version" && parts.len() == 2 {
-                if parts[1].parse::<usize>()? != 1 {
-                    return Err(super::ReadError::FormatErr);
+            if parts[0] == "Version" {
+                expect_values(&parts, 1, line_n)?;
+                if parse::<usize>(parts[1], line_n)? != 1 {
+                    return Err(ReadError::format(
+                        line_n,
+                        parts[1],
+                        FormatErrorKind::InvalidValue,
+                    ));
                 }
                 continue;
-            } else if parts[0] == "From" && parts.len() == 3 {
-                range_min = parts[1].parse::<f32>()?;
-                range_max = parts[2].parse::<f32>()?;
+            } else if parts[0] == "From" {
+                expect_values(&parts, 2, line_n)?;
+                range_min = parse_finite(parts[1], line_n)?;
+                range_max = parse_finite(parts[2], line_n)?;
                 continue;
-            } else if parts[0] == "Components" && parts.len() == 2 {
-                components = parts[1].parse::<usize>()?;
+            } else if parts[0] == "Components" {
+                expect_values(&parts, 1, line_n)?;
+                components = parse::<usize>(parts[1], line_n)?;
+                if !(1..=3).contains(&components) {
+                    return Err(ReadError::format(
+                        line_n,
+                        parts[1],
+                        FormatErrorKind::InvalidValue,
+                    ));
+                }
                 continue;
-            } else if parts[0] == "Length" && parts.len() == 2 {
-                length = parts[1].parse::<usize>()?;
+            } else if parts[0] == "Length" {
+                expect_values(&parts, 1, line_n)?;
+                length = parse::<usize>(parts[1], line_n)?;
+                length_line = line_n;
+                if length == 0 {
+                    return Err(ReadError::format(
+                        line_n,
+                        parts[1],
+                        FormatErrorKind::InvalidValue,
+                    ));
+                }
                 continue;
             } else if parts[0] == "{" && parts.len() == 1 {
                 // Ensure eveything adheres to the format.
-                if length == 0 || components < 1 || components > 3 {
-                    return Err(super::ReadError::FormatErr);
+                if length == 0 {
+                    return Err(ReadError::format(
+                        line_n,
+                        "Length",
+                        FormatErrorKind::MissingKeyword,
+                    ));
+                }
+                if components == 0 {
+                    return Err(ReadError::format(
+                        line_n,
+                        "Components",
+                        FormatErrorKind::MissingKeyword,
+                    ));
                 }
                 // Prep the tables.
                 for _ in 0..components {
@@ -90,29 +143,40 @@ pub fn read<R: BufRead>(reader: R) -> Result<Lut1D, super::ReadError> {
                 continue;
             } else {
                 // Line didn't match any acceptable pattern.
-                return Err(super::ReadError::FormatErr);
+                return Err(ReadError::format(
+                    line_n,
+                    parts[0],
+                    FormatErrorKind::BadKeyword,
+                ));
             }
         } else if reading_table {
             if parts[0] == "}" {
                 break;
             } else if parts.len() == components {
                 for i in 0..components {
-                    tables[i].push(parts[i].parse::<f32>()?);
+                    tables[i].push(parse_finite(parts[i], line_n)?);
                 }
                 continue;
             } else {
                 // Line didn't match any acceptable pattern.
-                return Err(super::ReadError::FormatErr);
+                return Err(ReadError::format(
+                    line_n,
+                    line.trim(),
+                    FormatErrorKind::WrongValueCount {
+                        expected: components,
+                        found: parts.len(),
+                    },
+                ));
             }
         }
     }
 
-    if !tables.iter().flatten().all(|n| n.is_finite())
-        || !range_min.is_finite()
-        || !range_max.is_finite()
-    {
-        // Non-finite values in the file.
-        return Err(super::ReadError::FormatErr);
+    if tables.is_empty() {
+        return Err(ReadError::format(
+            line_n,
+            "{",
+            FormatErrorKind::MissingKeyword,
+        ));
     }
 
     if length == tables[0].len() {
@@ -121,6 +185,13 @@ pub fn read<R: BufRead>(reader: R) -> Result<Lut1D, super::ReadError> {
             tables: tables,
         })
     } else {
-        Err(super::ReadError::FormatErr)
+        Err(ReadError::format(
+            length_line,
+            "Length",
+            FormatErrorKind::SizeMismatch {
+                expected: length,
+                found: tables[0].len(),
+            },
+        ))
     }
 }
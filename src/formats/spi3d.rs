@@ -0,0 +1,304 @@
+//! Sony Pictures Imageworks' 3D LUT format.
+//!
+//! Each entry in the file is listed along with its explicit lattice
+//! indices, so entries can technically be in any order.  The input
+//! range is always `[0.0, 1.0]`.
+
+use std::io::{BufRead, Write};
+
+use super::{expect_values, filter_non_finite, parse, parse_finite, FormatErrorKind, ReadError};
+use crate::lut::{validate_3d, Lut3D};
+
+/// Writes an SPI 3D LUT file.
+///
+/// The tables should have a length of
+/// `resolution[0] * resolution[1] * resolution[2]`, and their indices
+/// should be ordered the same as the `Lut3D` type.  Invalid LUT data is
+/// reported as an error of kind `InvalidInput`.
+pub fn write<W: Write>(
+    mut writer: W,
+    resolution: [usize; 3],
+    tables: [&[f32]; 3],
+) -> std::io::Result<()> {
+    validate_3d(&[(0.0, 1.0); 3], resolution, &tables)?;
+    let [res_r, res_g, res_b] = resolution;
+
+    writer.write_all(b"SPILUT 1.0\n")?;
+    writer.write_all(b"3 3\n")?;
+    writer.write_all(format!("{} {} {}\n", res_r, res_g, res_b).as_bytes())?;
+
+    // Write with blue changing fastest, which is the conventional
+    // ordering for this format.
+    for ri in 0..res_r {
+        for gi in 0..res_g {
+            for bi in 0..res_b {
+                let i = ri + (gi * res_r) + (bi * res_r * res_g);
+                writer.write_all(
+                    format!(
+                        "{} {} {} {} {} {}\n",
+                        ri,
+                        gi,
+                        bi,
+                        filter_non_finite(tables[0][i]),
+                        filter_non_finite(tables[1][i]),
+                        filter_non_finite(tables[2][i]),
+                    )
+                    .as_bytes(),
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads an SPI 3D LUT file.
+///
+/// Every lattice point must be listed exactly once.  Memory is only
+/// allocated for the entries actually present in the file, regardless
+/// of the resolution in the header.
+pub fn read<R: BufRead>(reader: R) -> Result<Lut3D, ReadError> {
+    let mut resolution = None;
+    let mut resolution_line = 0;
+    let mut len = 0;
+    // `(index, line number, rgb)` of each entry, in file order.
+    let mut entries: Vec<(usize, usize, [f32; 3])> = Vec::new();
+    let mut header_lines = 0;
+    let mut line_n = 0;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        line_n = i + 1;
+        let parts: Vec<_> = line.split_whitespace().collect();
+
+        if parts.is_empty() {
+            continue;
+        }
+
+        if header_lines == 0 {
+            if parts[0] != "SPILUT" {
+                return Err(ReadError::format(
+                    line_n,
+                    parts[0],
+                    FormatErrorKind::BadKeyword,
+                ));
+            }
+            expect_values(&parts, 1, line_n)?;
+            header_lines += 1;
+            continue;
+        } else if header_lines == 1 {
+            if parts.len() != 2 {
+                return Err(ReadError::format(
+                    line_n,
+                    line.trim(),
+                    FormatErrorKind::WrongValueCount {
+                        expected: 2,
+                        found: parts.len(),
+                    },
+                ));
+            }
+            for part in parts.iter() {
+                if parse::<usize>(part, line_n)? != 3 {
+                    return Err(ReadError::format(
+                        line_n,
+                        part,
+                        FormatErrorKind::InvalidValue,
+                    ));
+                }
+            }
+            header_lines += 1;
+            continue;
+        } else if header_lines == 2 {
+            if parts.len() != 3 {
+                return Err(ReadError::format(
+                    line_n,
+                    line.trim(),
+                    FormatErrorKind::WrongValueCount {
+                        expected: 3,
+                        found: parts.len(),
+                    },
+                ));
+            }
+            let mut res = [0usize; 3];
+            for (r, part) in res.iter_mut().zip(parts.iter()) {
+                *r = parse::<usize>(part, line_n)?;
+                if *r < 2 {
+                    return Err(ReadError::format(
+                        line_n,
+                        part,
+                        FormatErrorKind::InvalidValue,
+                    ));
+                }
+            }
+            len = match res[0]
+                .checked_mul(res[1])
+                .and_then(|n| n.checked_mul(res[2]))
+            {
+                Some(len) => len,
+                None => {
+                    return Err(ReadError::format(
+                        line_n,
+                        line.trim(),
+                        FormatErrorKind::InvalidValue,
+                    ))
+                }
+            };
+            resolution = Some(res);
+            resolution_line = line_n;
+            header_lines += 1;
+            continue;
+        }
+
+        let res = resolution.unwrap();
+        if parts.len() != 6 {
+            // Line didn't match any acceptable pattern.
+            return Err(ReadError::format(
+                line_n,
+                line.trim(),
+                FormatErrorKind::WrongValueCount {
+                    expected: 6,
+                    found: parts.len(),
+                },
+            ));
+        }
+        let mut index = [0usize; 3];
+        for axis in 0..3 {
+            index[axis] = parse::<usize>(parts[axis], line_n)?;
+            if index[axis] >= res[axis] {
+                // Index out of bounds.
+                return Err(ReadError::format(
+                    line_n,
+                    parts[axis],
+                    FormatErrorKind::InvalidValue,
+                ));
+            }
+        }
+
+        let i = index[0] + (index[1] * res[0]) + (index[2] * res[0] * res[1]);
+        entries.push((
+            i,
+            line_n,
+            [
+                parse_finite(parts[3], line_n)?,
+                parse_finite(parts[4], line_n)?,
+                parse_finite(parts[5], line_n)?,
+            ],
+        ));
+    }
+
+    let resolution = match resolution {
+        Some(res) => res,
+        None => {
+            return Err(ReadError::format(
+                line_n,
+                "",
+                FormatErrorKind::UnexpectedEnd,
+            ))
+        }
+    };
+
+    // Put the entries in lattice order, and ensure every lattice point
+    // was present exactly once.
+    entries.sort_by_key(|entry| entry.0);
+    for pair in entries.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            // Duplicate entry.
+            return Err(ReadError::format(
+                pair[1].1,
+                "",
+                FormatErrorKind::InvalidValue,
+            ));
+        }
+    }
+    if entries.len() != len {
+        return Err(ReadError::format(
+            resolution_line,
+            "",
+            FormatErrorKind::SizeMismatch {
+                expected: len,
+                found: entries.len(),
+            },
+        ));
+    }
+
+    let mut tables = vec![
+        Vec::with_capacity(len),
+        Vec::with_capacity(len),
+        Vec::with_capacity(len),
+    ];
+    for (_, _, rgb) in entries.iter() {
+        for (table, n) in tables.iter_mut().zip(rgb.iter()) {
+            table.push(*n);
+        }
+    }
+
+    let lut = Lut3D {
+        range: [(0.0, 1.0); 3],
+        resolution,
+        tables,
+    };
+    lut.validate()
+        .map_err(|e| ReadError::invalid_lut(resolution_line, "", e))?;
+
+    Ok(lut)
+}
+
+//-------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_read_round_trip() {
+        let lut = Lut3D::from_fn([2, 3, 4], [0.0; 3], [1.0; 3], |(r, g, b)| {
+            (r * 0.5, g * g, b + 0.25)
+        });
+
+        let mut data = Vec::new();
+        write(
+            &mut data,
+            lut.resolution,
+            [&lut.tables[0], &lut.tables[1], &lut.tables[2]],
+        )
+        .unwrap();
+        let lut2 = read(&data[..]).unwrap();
+
+        assert_eq!(lut2.resolution, lut.resolution);
+        assert_eq!(lut2.range, lut.range);
+        assert_eq!(lut2.tables, lut.tables);
+    }
+
+    #[test]
+    fn read_missing_and_duplicate_entries() {
+        let missing = "SPILUT 1.0\n3 3\n2 2 2\n0 0 0 0.0 0.0 0.0\n";
+        assert!(read(missing.as_bytes()).is_err());
+
+        let duplicate = "SPILUT 1.0\n3 3\n2 2 2\n0 0 0 0 0 0\n0 0 0 0 0 0\n";
+        assert!(read(duplicate.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn read_huge_resolution() {
+        // Must be an error, not an overflow or a huge allocation.
+        let data = "SPILUT 1.0\n3 3\n100000 100000 100000\n0 0 0 0 0 0\n";
+        assert!(matches!(
+            read(data.as_bytes()),
+            Err(ReadError::FormatErr {
+                line: 3,
+                kind: FormatErrorKind::SizeMismatch { .. },
+                ..
+            })
+        ));
+
+        let data = format!("SPILUT 1.0\n3 3\n{} {} 2\n", usize::MAX / 2, usize::MAX / 2);
+        assert!(matches!(
+            read(data.as_bytes()),
+            Err(ReadError::FormatErr {
+                line: 3,
+                kind: FormatErrorKind::InvalidValue,
+                ..
+            })
+        ));
+    }
+}
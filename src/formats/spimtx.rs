@@ -0,0 +1,108 @@
+//! Sony Pictures Imageworks' matrix format.
+//!
+//! The file consists of twelve numbers: a 3x4 matrix, where the last
+//! column is an offset that is added after the 3x3 part is applied.
+//! The offsets are stored scaled up by 65535, so an offset of 1.0 is
+//! written as 65535.
+
+use std::io::{BufRead, Write};
+
+use super::{parse_finite, FormatErrorKind, ReadError};
+use crate::matrix::Matrix;
+
+const OFFSET_SCALE: f64 = 65535.0;
+
+/// Writes an SPI matrix file.
+///
+/// `offset` is added after `matrix` is applied, and should be given
+/// unscaled (i.e. the 65535 scaling is done by this function).
+///
+/// NaN or infinite values (including offsets that become infinite when
+/// scaled) are reported as an error of kind `InvalidInput`.
+pub fn write<W: Write>(mut writer: W, matrix: Matrix, offset: [f64; 3]) -> std::io::Result<()> {
+    let non_finite = matrix.iter().flatten().any(|n| !n.is_finite())
+        || offset.iter().any(|n| !(n * OFFSET_SCALE).is_finite());
+    if non_finite {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "matrix and offset values must be finite",
+        ));
+    }
+
+    for (row, off) in matrix.iter().zip(offset.iter()) {
+        writer.write_all(
+            format!("{} {} {} {}\n", row[0], row[1], row[2], off * OFFSET_SCALE).as_bytes(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Reads an SPI matrix file.
+///
+/// Returns the 3x3 matrix and the offset vector.  The offset has had
+/// the format's 65535 scaling removed.
+pub fn read<R: BufRead>(reader: R) -> Result<(Matrix, [f64; 3]), ReadError> {
+    let mut values = Vec::new();
+    let mut line_n = 0;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        line_n = i + 1;
+        for part in line.split_whitespace() {
+            values.push(parse_finite::<f64>(part, line_n)?);
+        }
+    }
+
+    if values.len() != 12 {
+        return Err(ReadError::format(
+            line_n,
+            "",
+            FormatErrorKind::WrongValueCount {
+                expected: 12,
+                found: values.len(),
+            },
+        ));
+    }
+
+    let mut matrix = [[0.0f64; 3]; 3];
+    let mut offset = [0.0f64; 3];
+    for (i, row) in values.chunks_exact(4).enumerate() {
+        matrix[i] = [row[0], row[1], row[2]];
+        offset[i] = row[3] / OFFSET_SCALE;
+    }
+
+    Ok((matrix, offset))
+}
+
+//-------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_01() {
+        let data = "0.5 0.25 0.25 0\n0 1 0 65535\n0.1 0.2 0.7 -32767.5\n";
+        let (matrix, offset) = read(data.as_bytes()).unwrap();
+
+        assert_eq!(
+            matrix,
+            [[0.5, 0.25, 0.25], [0.0, 1.0, 0.0], [0.1, 0.2, 0.7]]
+        );
+        assert_eq!(offset, [0.0, 1.0, -0.5]);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let matrix = crate::matrix::rgb_to_xyz_matrix(crate::chroma::REC709);
+        let offset = [0.125, -0.25, 0.0];
+
+        let mut data = Vec::new();
+        write(&mut data, matrix, offset).unwrap();
+        let (matrix2, offset2) = read(&data[..]).unwrap();
+
+        assert_eq!(matrix, matrix2);
+        assert_eq!(offset, offset2);
+    }
+}
@@ -1,5 +1,8 @@
 //! Types for storing and working with LUTs in memory.
 
+use crate::matrix::{transform_color, Matrix};
+use crate::transforms::oklab;
+
 /// A 1D look up table.
 ///
 /// The `ranges` specify the input range that the table indices map to.
@@ -24,6 +27,24 @@ impl Default for Lut1D {
 }
 
 impl Lut1D {
+    /// Creates a 1D LUT, checking that it's valid.
+    ///
+    /// See `validate()` for the checks that are done.
+    pub fn new(ranges: Vec<(f32, f32)>, tables: Vec<Vec<f32>>) -> Result<Lut1D, LutError> {
+        let lut = Lut1D { ranges, tables };
+        lut.validate()?;
+        Ok(lut)
+    }
+
+    /// Checks that the LUT is valid.
+    ///
+    /// Specifically, that it has at least one table, all tables have at
+    /// least two entries, there is either one range or one per table,
+    /// and all ranges are finite and non-empty.
+    pub fn validate(&self) -> Result<(), LutError> {
+        validate_1d(&self.ranges, &self.tables)
+    }
+
     /// Creates a single-component 1D LUT from a function and input range.
     pub fn from_fn_1<F: Fn(f32) -> f32>(points: usize, min_x: f32, max_x: f32, f: F) -> Lut1D {
         let inc = (max_x as f64 - min_x as f64) / (points - 1) as f64;
@@ -116,6 +137,40 @@ impl Lut1D {
         }
     }
 
+    /// Resamples the LUT to the given number of samples, keeping the
+    /// same input ranges.
+    ///
+    /// Returns an error if the LUT is invalid (see `validate()`), or if
+    /// `samples` is less than 2.
+    pub fn resample(
+        &self,
+        samples: usize,
+        interpolation: Interpolation1D,
+    ) -> Result<Lut1D, LutError> {
+        self.validate()?;
+        if samples < 2 {
+            return Err(LutError::TableTooShort {
+                index: 0,
+                len: samples,
+            });
+        }
+
+        let tables = self
+            .tables
+            .iter()
+            .enumerate()
+            .map(|(i, table)| {
+                let range = self.ranges[i.min(self.ranges.len() - 1)];
+                resample_with(samples, range, table, range, interpolation)
+            })
+            .collect();
+
+        Ok(Lut1D {
+            ranges: self.ranges.clone(),
+            tables,
+        })
+    }
+
     /// Resample the LUT such that all channels have the same input range.
     ///
     /// The input range of the new LUT will be the union of all the ranges
@@ -177,11 +232,36 @@ impl Lut1D {
         }
     }
 
+    /// Like `look_up()`, but with a choice of interpolation method.
+    ///
+    /// Note: this is a convenience function, and is not intended for high-performance
+    /// situations.
+    pub fn look_up_with(&self, n: f32, channel: usize, interpolation: Interpolation1D) -> f32 {
+        assert!(channel < self.tables.len());
+        assert!(self.ranges.len() == 1 || self.ranges.len() == self.tables.len());
+
+        let table = &self.tables[channel];
+        assert!(table.len() >= 2);
+        let range = if self.ranges.len() == 1 {
+            self.ranges[0]
+        } else {
+            self.ranges[channel]
+        };
+
+        let x = (n - range.0) / (range.1 - range.0) * (table.len() - 1) as f32;
+        sample_table(table, x, interpolation)
+    }
+
     /// Does the inverse of `look_up()`.
     ///
     /// In other words, `n == lut.lookup_inv(lut.look_up(n, 0), 0)`.
     ///
-    /// This assumes that the LUT is monotonically increasing.
+    /// This assumes that the LUT is monotonically increasing, and gives
+    /// meaningless results if it isn't.  See `try_look_up_inv()` for
+    /// handling other tables.
+    ///
+    /// NaN inputs give NaN, as do inputs that are interpolated against a
+    /// NaN table value.
     ///
     /// Note: this is a convenience function, and is not intended for high-performance
     /// situations.
@@ -190,22 +270,25 @@ impl Lut1D {
         assert!(self.ranges.len() == 1 || self.ranges.len() == self.tables.len());
 
         let table = &self.tables[channel];
+        assert!(table.len() >= 2);
         let range = if self.ranges.len() == 1 {
             self.ranges[0]
         } else {
             self.ranges[channel]
         };
 
-        let (i1, i2) = match table.binary_search_by(|v| v.partial_cmp(&n).unwrap()) {
-            Ok(i) => (i - 1, i),
-            Err(i) => {
-                if i == 0 {
-                    (i, i + 1)
-                } else {
-                    (i - 1, i)
-                }
-            }
+        if n.is_nan() {
+            return f32::NAN;
+        }
+
+        // NaN table values are treated as less than everything.
+        let i = match table
+            .binary_search_by(|v| v.partial_cmp(&n).unwrap_or(std::cmp::Ordering::Less))
+        {
+            Ok(i) | Err(i) => i,
         };
+        let i1 = i.clamp(1, table.len() - 1) - 1;
+        let i2 = i1 + 1;
 
         let out_1 = i1 as f32 / (table.len() - 1) as f32;
         let out_2 = i2 as f32 / (table.len() - 1) as f32;
@@ -221,6 +304,105 @@ impl Lut1D {
         (t * (range.1 - range.0)) + range.0
     }
 
+    /// Like `look_up_inv()`, but handles tables that aren't
+    /// monotonically increasing according to `strategy`, and returns an
+    /// error instead of garbage for tables that can't be inverted.
+    ///
+    /// Inputs outside of the table's output values are clamped to them.
+    /// Where a flat span of the table matches the input exactly, the
+    /// middle of the span is returned (or the start/end of it for the
+    /// first/last crossing strategies).
+    pub fn try_look_up_inv(
+        &self,
+        n: f32,
+        channel: usize,
+        strategy: InversionStrategy,
+    ) -> Result<f32, InversionError> {
+        assert!(channel < self.tables.len());
+        assert!(self.ranges.len() == 1 || self.ranges.len() == self.tables.len());
+
+        let range = self.ranges[channel.min(self.ranges.len() - 1)];
+        let inverter = TableInverter::new(&self.tables[channel], range, channel, strategy)?;
+        Ok(inverter.look_up(n))
+    }
+
+    /// Like `resample_inverted()`, but handles tables that aren't
+    /// monotonically increasing according to `strategy`, and returns an
+    /// error instead of garbage for tables that can't be inverted.
+    ///
+    /// The new input ranges span the smallest to largest values of the
+    /// tables.
+    pub fn try_resample_inverted(
+        &self,
+        samples: usize,
+        strategy: InversionStrategy,
+    ) -> Result<Lut1D, InversionError> {
+        assert!(samples >= 2);
+        assert!(self.ranges.len() == 1 || self.ranges.len() == self.tables.len());
+
+        let mut inverters = Vec::with_capacity(self.tables.len());
+        for (chan, table) in self.tables.iter().enumerate() {
+            let range = self.ranges[chan.min(self.ranges.len() - 1)];
+            inverters.push(TableInverter::new(table, range, chan, strategy)?);
+        }
+
+        let value_range = |inverter: &TableInverter| {
+            inverter
+                .table
+                .iter()
+                .fold((f32::INFINITY, -f32::INFINITY), |a, v| {
+                    (a.0.min(*v), a.1.max(*v))
+                })
+        };
+        let ranges: Vec<(f32, f32)> = if self.ranges.len() == 1 {
+            vec![inverters
+                .iter()
+                .map(value_range)
+                .fold((f32::INFINITY, -f32::INFINITY), |a, b| {
+                    (a.0.min(b.0), a.1.max(b.1))
+                })]
+        } else {
+            inverters.iter().map(value_range).collect()
+        };
+
+        let tables = inverters
+            .iter()
+            .enumerate()
+            .map(|(chan, inverter)| {
+                let range = ranges[chan.min(ranges.len() - 1)];
+                let inc = (range.1 as f64 - range.0 as f64) / (samples - 1) as f64;
+                (0..samples)
+                    .map(|i| inverter.look_up(range.0 + (inc * i as f64) as f32))
+                    .collect()
+            })
+            .collect();
+
+        Ok(Lut1D { ranges, tables })
+    }
+
+    /// Applies the LUT to a buffer of RGB values in place.
+    ///
+    /// The LUT must have either one table, which is then applied to all
+    /// three channels, or three tables.  Inputs outside of the LUT's
+    /// ranges are clamped to them.
+    pub fn apply(&self, pixels: &mut [[f32; 3]]) {
+        let sampler = Sampler1D::new(self);
+        for rgb in pixels.iter_mut() {
+            *rgb = sampler.look_up(*rgb);
+        }
+    }
+
+    /// Applies the LUT in place to a buffer of interleaved pixels with
+    /// `channels` channels each, e.g. 3 for RGB or 4 for RGBA.
+    ///
+    /// The first three channels of each pixel are treated as RGB, and any
+    /// further channels (e.g. alpha) are left untouched.  Otherwise the
+    /// same as `apply()`.
+    pub fn apply_interleaved(&self, data: &mut [f32], channels: usize) {
+        let sampler = Sampler1D::new(self);
+        for_each_rgb(data, channels, |rgb| sampler.look_up(rgb));
+    }
+
     /// Checks whether the LUT is monotonically increasing or not.
     ///
     /// Note: this has nothing to do with monotone color.
@@ -239,68 +421,2465 @@ impl Lut1D {
     }
 }
 
-/// A 3D lookup table.
+/// A violation of the invariants of `Lut1D` or `Lut3D`, as reported by
+/// their `validate()` methods and checked constructors.
 ///
-/// `range` specifies the range of the input cube coordinates on all
-/// three axes.  `resolution` specifies the number of samples in each
-/// dimension.
+/// The LUT writers in `formats` also report invalid input with this,
+/// wrapped in an `std::io::Error` of kind `InvalidInput`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LutError {
+    /// There are no tables.
+    NoTables,
+    /// The wrong number of tables.
+    WrongTableCount { expected: usize, found: usize },
+    /// The number of ranges is neither 1 nor the number of tables.
+    RangeCountMismatch { ranges: usize, tables: usize },
+    /// A range has a non-finite bound, or its start isn't less than its
+    /// end.  `index` is the index of the range (or the axis, for 3D
+    /// LUTs).
+    BadRange { index: usize },
+    /// A 1D table has fewer than two entries.
+    TableTooShort { index: usize, len: usize },
+    /// A table's length doesn't match the resolution or the other
+    /// tables.
+    TableLengthMismatch {
+        index: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A 3D LUT has a resolution of less than 2 on an axis.
+    ResolutionTooLow { axis: usize, resolution: usize },
+    /// A 3D LUT doesn't have the same resolution on all axes, as
+    /// required by some file formats.
+    NonCubicResolution,
+    /// A 3D LUT's resolution is too large for its number of lattice
+    /// points to be represented.
+    ResolutionTooHigh,
+}
+
+impl std::fmt::Display for LutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LutError::NoTables => write!(f, "LUT has no tables"),
+            LutError::WrongTableCount { expected, found } => {
+                write!(f, "expected {} tables, found {}", expected, found)
+            }
+            LutError::RangeCountMismatch { ranges, tables } => write!(
+                f,
+                "{} ranges for {} tables, expected 1 range or one per table",
+                ranges, tables
+            ),
+            LutError::BadRange { index } => write!(f, "range {} is empty or non-finite", index),
+            LutError::TableTooShort { index, len } => {
+                write!(
+                    f,
+                    "table {} has {} entries, expected at least 2",
+                    index, len
+                )
+            }
+            LutError::TableLengthMismatch {
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "table {} has {} entries, expected {}",
+                index, found, expected
+            ),
+            LutError::ResolutionTooLow { axis, resolution } => write!(
+                f,
+                "resolution {} on axis {}, expected at least 2",
+                resolution, axis
+            ),
+            LutError::NonCubicResolution => write!(f, "resolution differs between axes"),
+            LutError::ResolutionTooHigh => write!(f, "resolution is too large"),
+        }
+    }
+}
+
+impl std::error::Error for LutError {}
+
+impl From<LutError> for std::io::Error {
+    fn from(error: LutError) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, error)
+    }
+}
+
+fn validate_range(range: (f32, f32), index: usize) -> Result<(), LutError> {
+    if range.0.is_finite() && range.1.is_finite() && range.0 < range.1 {
+        Ok(())
+    } else {
+        Err(LutError::BadRange { index })
+    }
+}
+
+/// Checks the invariants of `Lut1D`, for tables that may not be in one.
+pub(crate) fn validate_1d<T: AsRef<[f32]>>(
+    ranges: &[(f32, f32)],
+    tables: &[T],
+) -> Result<(), LutError> {
+    if tables.is_empty() {
+        return Err(LutError::NoTables);
+    }
+    if ranges.len() != 1 && ranges.len() != tables.len() {
+        return Err(LutError::RangeCountMismatch {
+            ranges: ranges.len(),
+            tables: tables.len(),
+        });
+    }
+    for (i, range) in ranges.iter().enumerate() {
+        validate_range(*range, i)?;
+    }
+    for (i, table) in tables.iter().enumerate() {
+        let len = table.as_ref().len();
+        if len < 2 {
+            return Err(LutError::TableTooShort { index: i, len });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the invariants of `Lut3D`, for tables that may not be in one.
+pub(crate) fn validate_3d<T: AsRef<[f32]>>(
+    range: &[(f32, f32); 3],
+    resolution: [usize; 3],
+    tables: &[T],
+) -> Result<(), LutError> {
+    if tables.len() != 3 {
+        return Err(LutError::WrongTableCount {
+            expected: 3,
+            found: tables.len(),
+        });
+    }
+    let len = validate_lattice(range, resolution)?;
+    validate_table_lengths(tables, len)
+}
+
+/// Checks the range and resolution of a 3D LUT, e.g. one that's about
+/// to be built.
 ///
-/// `tables` contains a table for each output component.  Each table has
-/// `resolution[0] * resolution[1] * resolution[2]` elements.  The table
-/// data is laid out such that the following formula can be used to
-/// compute the index of the element at `x,y,z` (or `r,g,b`, etc.):
+/// Returns the number of lattice points.
+pub(crate) fn validate_lattice(
+    range: &[(f32, f32); 3],
+    resolution: [usize; 3],
+) -> Result<usize, LutError> {
+    for (axis, r) in range.iter().enumerate() {
+        validate_range(*r, axis)?;
+    }
+    for (axis, res) in resolution.iter().enumerate() {
+        if *res < 2 {
+            return Err(LutError::ResolutionTooLow {
+                axis,
+                resolution: *res,
+            });
+        }
+    }
+    resolution[0]
+        .checked_mul(resolution[1])
+        .and_then(|n| n.checked_mul(resolution[2]))
+        .ok_or(LutError::ResolutionTooHigh)
+}
+
+/// Checks that a 1D LUT is valid and has either one table or three, as
+/// needed for applying it to RGB values.
+fn validate_1d_rgb(lut: &Lut1D) -> Result<(), LutError> {
+    lut.validate()?;
+    if lut.tables.len() != 1 && lut.tables.len() != 3 {
+        return Err(LutError::WrongTableCount {
+            expected: 3,
+            found: lut.tables.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that all tables have `len` entries.
+pub(crate) fn validate_table_lengths<T: AsRef<[f32]>>(
+    tables: &[T],
+    len: usize,
+) -> Result<(), LutError> {
+    for (i, table) in tables.iter().enumerate() {
+        if table.as_ref().len() != len {
+            return Err(LutError::TableLengthMismatch {
+                index: i,
+                expected: len,
+                found: table.as_ref().len(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// How to invert 1D LUT tables that aren't monotonically increasing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InversionStrategy {
+    /// Return an error.
+    Error,
+    /// Make the table monotonically increasing before inverting it, by
+    /// raising each value to at least the values before it.
+    Monotonize,
+    /// Use the lowest input that maps to the value being inverted.
+    FirstCrossing,
+    /// Use the highest input that maps to the value being inverted.
+    LastCrossing,
+}
+
+/// An error from inverting a 1D LUT.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InversionError {
+    /// A table has a NaN or infinite value.
+    NonFiniteValue { channel: usize, index: usize },
+    /// A table isn't monotonically increasing.  `index` is the first
+    /// entry that's less than the one before it.
+    NonMonotonic { channel: usize, index: usize },
+    /// A table has fewer than two entries.
+    TooShort { channel: usize },
+}
+
+impl std::fmt::Display for InversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InversionError::NonFiniteValue { channel, index } => write!(
+                f,
+                "non-finite value in table {} at index {}",
+                channel, index
+            ),
+            InversionError::NonMonotonic { channel, index } => write!(
+                f,
+                "table {} is not monotonically increasing at index {}",
+                channel, index
+            ),
+            InversionError::TooShort { channel } => {
+                write!(f, "table {} has fewer than two entries", channel)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InversionError {}
+
+/// A validated 1D LUT table, ready for inverse lookups.
+struct TableInverter<'a> {
+    /// The table, monotonized if needed.
+    table: std::borrow::Cow<'a, [f32]>,
+    range: (f32, f32),
+    strategy: InversionStrategy,
+    /// Whether `table` is monotonically increasing.
+    monotonic: bool,
+}
+
+impl<'a> TableInverter<'a> {
+    fn new(
+        table: &'a [f32],
+        range: (f32, f32),
+        channel: usize,
+        strategy: InversionStrategy,
+    ) -> Result<TableInverter<'a>, InversionError> {
+        if table.len() < 2 {
+            return Err(InversionError::TooShort { channel });
+        }
+        if let Some(index) = table.iter().position(|v| !v.is_finite()) {
+            return Err(InversionError::NonFiniteValue { channel, index });
+        }
+
+        let decreasing = table.windows(2).position(|w| w[1] < w[0]);
+        let table = match (decreasing, strategy) {
+            (None, _) => table.into(),
+            (Some(i), InversionStrategy::Error) => {
+                return Err(InversionError::NonMonotonic {
+                    channel,
+                    index: i + 1,
+                })
+            }
+            (Some(_), InversionStrategy::Monotonize) => {
+                let mut max = -f32::INFINITY;
+                table
+                    .iter()
+                    .map(|v| {
+                        max = max.max(*v);
+                        max
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+            (Some(_), _) => table.into(),
+        };
+
+        Ok(TableInverter {
+            monotonic: decreasing.is_none() || strategy == InversionStrategy::Monotonize,
+            table,
+            range,
+            strategy,
+        })
+    }
+
+    /// Returns the input for table index `i`, which may be fractional.
+    fn x(&self, i: f32) -> f32 {
+        let t = i / (self.table.len() - 1) as f32;
+        self.range.0 + ((self.range.1 - self.range.0) * t)
+    }
+
+    fn look_up(&self, n: f32) -> f32 {
+        if n.is_nan() {
+            return f32::NAN;
+        }
+        let table = &self.table[..];
+        let last = table.len() - 1;
+
+        if !self.monotonic {
+            return if self.strategy == InversionStrategy::LastCrossing {
+                self.crossing(n, (0..last).rev())
+                    .unwrap_or_else(|| self.closest(n, true))
+            } else {
+                self.crossing(n, 0..last)
+                    .unwrap_or_else(|| self.closest(n, false))
+            };
+        }
+
+        let i1 = table.partition_point(|v| *v < n);
+        let i2 = table.partition_point(|v| *v <= n);
+        if i1 != i2 {
+            // One or more entries exactly match.
+            match self.strategy {
+                InversionStrategy::FirstCrossing => self.x(i1 as f32),
+                InversionStrategy::LastCrossing => self.x((i2 - 1) as f32),
+                _ => self.x((i1 + i2 - 1) as f32 * 0.5),
+            }
+        } else if i1 == 0 {
+            self.range.0
+        } else if i1 > last {
+            self.range.1
+        } else {
+            let alpha = (n - table[i1 - 1]) / (table[i1] - table[i1 - 1]);
+            self.x((i1 - 1) as f32 + alpha)
+        }
+    }
+
+    /// Finds the crossing of `n` in the first matching interval of
+    /// `intervals`, in the order given.
+    fn crossing<I: Iterator<Item = usize>>(&self, n: f32, intervals: I) -> Option<f32> {
+        let table = &self.table[..];
+        let reverse = self.strategy == InversionStrategy::LastCrossing;
+        for i in intervals {
+            let (v1, v2) = (table[i], table[i + 1]);
+            // Check the near end of the interval for an exact match
+            // first, so flat spans resolve to their near end.
+            let (near, near_i) = if reverse { (v2, i + 1) } else { (v1, i) };
+            if near == n {
+                return Some(self.x(near_i as f32));
+            }
+            if (v1 < n && n < v2) || (v2 < n && n < v1) {
+                let alpha = (n - v1) / (v2 - v1);
+                return Some(self.x(i as f32 + alpha));
+            }
+        }
+
+        // The far end of the last interval.
+        let far_i = if reverse { 0 } else { table.len() - 1 };
+        if table[far_i] == n {
+            Some(self.x(far_i as f32))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the input of the entry with the value closest to `n`,
+    /// preferring the first or last of equally close entries.
+    fn closest(&self, n: f32, last: bool) -> f32 {
+        let mut best = 0;
+        for (i, v) in self.table.iter().enumerate() {
+            let (d, best_d) = ((v - n).abs(), (self.table[best] - n).abs());
+            if d < best_d || (last && d == best_d) {
+                best = i;
+            }
+        }
+        self.x(best as f32)
+    }
+}
+
+/// A 1D look up table with non-uniformly spaced samples.
 ///
-/// ```ignore
-/// index = x + (y * resolution[0]) + (z * resolution[0] * resolution[1]);
-/// ```
+/// Each table is a list of `(x, y)` breakpoints, and is linearly
+/// interpolated between them.  The `x` values of each table must be
+/// strictly increasing.  Inputs outside of a table's breakpoints are
+/// given its first/last `y` value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lut1DNonUniform {
+    pub tables: Vec<Vec<(f32, f32)>>,
+}
+
+impl Lut1DNonUniform {
+    /// Creates a non-uniform LUT with the same samples as a `Lut1D`.
+    ///
+    /// This is lossless: each sample of `lut` becomes a breakpoint.
+    ///
+    /// Returns an error if `lut` is invalid.
+    pub fn from_lut_1d(lut: &Lut1D) -> Result<Lut1DNonUniform, LutError> {
+        lut.validate()?;
+
+        let tables = lut
+            .tables
+            .iter()
+            .enumerate()
+            .map(|(i, table)| {
+                let range = lut.ranges[i.min(lut.ranges.len() - 1)];
+                let inc = (range.1 as f64 - range.0 as f64) / (table.len() - 1) as f64;
+                table
+                    .iter()
+                    .enumerate()
+                    .map(|(j, y)| (range.0 + (inc * j as f64) as f32, *y))
+                    .collect()
+            })
+            .collect();
+
+        Ok(Lut1DNonUniform { tables })
+    }
+
+    /// Converts to a uniformly sampled `Lut1D` with the given number of
+    /// samples.
+    ///
+    /// The resulting LUT has a single input range that is the union of
+    /// the input ranges of the tables, as needed by e.g. the Resolve .cube
+    /// format.
+    pub fn to_lut_1d(&self, samples: usize) -> Lut1D {
+        assert!(samples >= 2);
+        assert!(self.tables.iter().all(|t| !t.is_empty()));
+
+        let range = self
+            .tables
+            .iter()
+            .fold((f32::INFINITY, -f32::INFINITY), |a, t| {
+                (a.0.min(t[0].0), a.1.max(t.last().unwrap().0))
+            });
+        let inc = (range.1 as f64 - range.0 as f64) / (samples - 1) as f64;
+
+        let tables = (0..self.tables.len())
+            .map(|chan| {
+                (0..samples)
+                    .map(|i| self.look_up(range.0 + (inc * i as f64) as f32, chan))
+                    .collect()
+            })
+            .collect();
+
+        Lut1D {
+            ranges: vec![range],
+            tables,
+        }
+    }
+
+    /// Does a single-channel lookup.
+    pub fn look_up(&self, n: f32, channel: usize) -> f32 {
+        let table = &self.tables[channel];
+        assert!(!table.is_empty());
+
+        let i = table.partition_point(|(x, _)| *x <= n);
+        if i == 0 {
+            table[0].1
+        } else if i == table.len() {
+            table.last().unwrap().1
+        } else {
+            let (x1, y1) = table[i - 1];
+            let (x2, y2) = table[i];
+            let alpha = (n - x1) / (x2 - x1);
+            y1 + ((y2 - y1) * alpha)
+        }
+    }
+
+    /// Does the inverse of `look_up()`.
+    ///
+    /// This assumes that the table's `y` values are monotonically
+    /// increasing.  Inputs outside of the `y` values are given the
+    /// first/last `x` value.  Where a flat span of the table matches the
+    /// input, the middle of the span is returned.
+    pub fn look_up_inv(&self, n: f32, channel: usize) -> f32 {
+        let table = &self.tables[channel];
+        assert!(!table.is_empty());
+
+        let i1 = table.partition_point(|(_, y)| *y < n);
+        let i2 = table.partition_point(|(_, y)| *y <= n);
+        if i1 != i2 {
+            // One or more breakpoints exactly match.
+            (table[i1].0 + table[i2 - 1].0) * 0.5
+        } else if i1 == 0 {
+            table[0].0
+        } else if i1 == table.len() {
+            table.last().unwrap().0
+        } else {
+            let (x1, y1) = table[i1 - 1];
+            let (x2, y2) = table[i1];
+            let alpha = (n - y1) / (y2 - y1);
+            x1 + ((x2 - x1) * alpha)
+        }
+    }
+}
+
+/// A 1D look up table indexed by the bits of half-precision (f16)
+/// floating point inputs.
+///
+/// Each table has exactly `Lut1DHalfDomain::SAMPLES` entries: one for
+/// every f16 bit pattern, with the entry for an input at the index of
+/// its f16 bits.  This covers the entire f16 range, including negative
+/// and very large values, and is the same as the "half domain" 1D LUTs
+/// of CLF and OpenColorIO.
+///
+/// Inputs between f16 values are linearly interpolated between the
+/// entries of the nearest f16 values on either side.
 #[derive(Debug, Clone)]
-pub struct Lut3D {
-    pub range: [(f32, f32); 3],
-    pub resolution: [usize; 3],
+pub struct Lut1DHalfDomain {
     pub tables: Vec<Vec<f32>>,
 }
 
-impl Lut3D {
-    pub fn from_fn<F: Fn((f32, f32, f32)) -> (f32, f32, f32)>(
-        resolution: [usize; 3],
-        min: [f32; 3],
-        max: [f32; 3],
-        f: F,
-    ) -> Lut3D {
-        let inc = [
-            (max[0] as f64 - min[0] as f64) / (resolution[0] - 1) as f64,
-            (max[1] as f64 - min[1] as f64) / (resolution[1] - 1) as f64,
-            (max[2] as f64 - min[2] as f64) / (resolution[2] - 1) as f64,
-        ];
-        let mut tables = vec![Vec::new(), Vec::new(), Vec::new()];
-        for zi in 0..resolution[2] {
-            for yi in 0..resolution[1] {
-                for xi in 0..resolution[0] {
-                    let x_in = min[0] + (inc[0] * xi as f64) as f32;
-                    let y_in = min[1] + (inc[1] * yi as f64) as f32;
-                    let z_in = min[2] + (inc[2] * zi as f64) as f32;
-                    let (x, y, z) = f((x_in, y_in, z_in));
-                    tables[0].push(x);
-                    tables[1].push(y);
-                    tables[2].push(z);
-                }
+impl Lut1DHalfDomain {
+    /// The number of entries in each table.
+    pub const SAMPLES: usize = 1 << 16;
+
+    /// Creates a single-component half-domain LUT from a function.
+    ///
+    /// Non-finite entries are avoided, so that the LUT can be written to
+    /// any file format:
+    ///
+    /// - Entries for NaN inputs are 0.0.
+    /// - Entries for infinite inputs are `f(±inf)` if that's finite, and
+    ///   otherwise the same as the entries for the largest finite inputs.
+    /// - NaN outputs of `f` are replaced with 0.0, and infinite outputs
+    ///   are clamped to the largest finite f32 values.
+    pub fn from_fn_1<F: Fn(f32) -> f32>(f: F) -> Lut1DHalfDomain {
+        Lut1DHalfDomain {
+            tables: vec![half_domain_table(f)],
+        }
+    }
+
+    /// Creates a 3-component half-domain LUT from three functions.
+    ///
+    /// Non-finite entries are handled the same as in `from_fn_1()`.
+    pub fn from_fn_3<F1, F2, F3>(fs: (F1, F2, F3)) -> Lut1DHalfDomain
+    where
+        F1: Fn(f32) -> f32,
+        F2: Fn(f32) -> f32,
+        F3: Fn(f32) -> f32,
+    {
+        Lut1DHalfDomain {
+            tables: vec![
+                half_domain_table(fs.0),
+                half_domain_table(fs.1),
+                half_domain_table(fs.2),
+            ],
+        }
+    }
+
+    /// Does a single-channel lookup.
+    ///
+    /// NaN inputs return the entry for NaN, infinite inputs return the
+    /// entries for infinity, and finite inputs beyond the f16 range are
+    /// clamped to it.
+    pub fn look_up(&self, n: f32, channel: usize) -> f32 {
+        let table = &self.tables[channel];
+        assert!(table.len() == Self::SAMPLES);
+
+        if n.is_nan() {
+            return table[HALF_NAN as usize];
+        } else if n.is_infinite() {
+            let sign = if n < 0.0 { HALF_SIGN } else { 0 };
+            return table[(HALF_INF | sign) as usize];
+        }
+
+        let sign = if n.is_sign_negative() { HALF_SIGN } else { 0 };
+        let a = n.abs().min(HALF_MAX);
+        let bits_1 = f32_to_f16_bits_trunc(a);
+        if bits_1 == HALF_MAX_BITS {
+            return table[(bits_1 | sign) as usize];
+        }
+        let bits_2 = bits_1 + 1;
+
+        let a1 = f16_bits_to_f32(bits_1);
+        let a2 = f16_bits_to_f32(bits_2);
+        let alpha = (a - a1) / (a2 - a1);
+        let v1 = table[(bits_1 | sign) as usize];
+        let v2 = table[(bits_2 | sign) as usize];
+        v1 + ((v2 - v1) * alpha)
+    }
+
+    /// Does the inverse of `look_up()`.
+    ///
+    /// This assumes that the table is monotonically increasing over the
+    /// finite f16 inputs.  Values outside of the table's output range
+    /// are clamped to the finite f16 range, and NaN returns NaN.
+    pub fn look_up_inv(&self, n: f32, channel: usize) -> f32 {
+        let table = &self.tables[channel];
+        assert!(table.len() == Self::SAMPLES);
+
+        if n.is_nan() {
+            return f32::NAN;
+        }
+
+        // Finite f16 values are visited in increasing order via `k`.
+        let value = |k: usize| table[half_finite_bits(k) as usize];
+        let last = HALF_FINITE_COUNT - 1;
+        if n <= value(0) {
+            return -HALF_MAX;
+        } else if n >= value(last) {
+            return HALF_MAX;
+        }
+
+        // First index with a value greater than `n`.  Due to the checks
+        // above, this is in `1..=last`.
+        let k = {
+            let (mut low, mut high) = (0, last);
+            while low < high {
+                let mid = (low + high) / 2;
+                if value(mid) <= n {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            low
+        };
+
+        let x1 = f16_bits_to_f32(half_finite_bits(k - 1));
+        let x2 = f16_bits_to_f32(half_finite_bits(k));
+        let (v1, v2) = (value(k - 1), value(k));
+        let alpha = if v2 > v1 { (n - v1) / (v2 - v1) } else { 0.0 };
+        x1 + ((x2 - x1) * alpha)
+    }
+}
+
+const HALF_SIGN: u16 = 0x8000;
+const HALF_INF: u16 = 0x7c00;
+const HALF_NAN: u16 = 0x7e00;
+const HALF_MAX_BITS: u16 = 0x7bff;
+const HALF_MAX: f32 = 65504.0;
+
+/// The number of finite f16 values, counting zero twice.
+const HALF_FINITE_COUNT: usize = (HALF_MAX_BITS as usize + 1) * 2;
+
+/// Returns the bits of the `k`th finite f16 value, in increasing order
+/// from `-HALF_MAX` to `HALF_MAX`.
+fn half_finite_bits(k: usize) -> u16 {
+    let half = HALF_FINITE_COUNT / 2;
+    if k < half {
+        HALF_SIGN | (half - 1 - k) as u16
+    } else {
+        (k - half) as u16
+    }
+}
+
+/// Converts the bits of an f16 to an f32, exactly.
+pub(crate) fn f16_bits_to_f32(bits: u16) -> f32 {
+    let sign = ((bits & HALF_SIGN) as u32) << 16;
+    let exp = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+
+    let magnitude = if exp == 0 {
+        // Zero or subnormal.
+        mantissa as f32 * (1.0 / (1 << 24) as f32)
+    } else if exp == 0x1f {
+        // Infinity or NaN.
+        f32::from_bits(0x7f80_0000 | (mantissa << 13))
+    } else {
+        f32::from_bits(((exp + 127 - 15) << 23) | (mantissa << 13))
+    };
+    f32::from_bits(magnitude.to_bits() | sign)
+}
+
+/// Returns the bits of the largest f16 that is less than or equal to
+/// `n`, which must be in `[0.0, HALF_MAX]`.
+fn f32_to_f16_bits_trunc(n: f32) -> u16 {
+    debug_assert!((0.0..=HALF_MAX).contains(&n));
+    let exp = ((n.to_bits() >> 23) & 0xff) as i32 - 127;
+    if exp < -14 {
+        // Subnormal in f16.  Note that this rolls over correctly into
+        // the smallest normal f16.
+        (n * (1 << 24) as f32) as u16
+    } else {
+        let mantissa = ((n.to_bits() >> 13) & 0x3ff) as u16;
+        (((exp + 15) as u16) << 10) | mantissa
+    }
+}
+
+/// Builds a half-domain table from a function, as documented in
+/// `Lut1DHalfDomain::from_fn_1()`.
+fn half_domain_table<F: Fn(f32) -> f32>(f: F) -> Vec<f32> {
+    let finite = |n: f32| {
+        if n.is_nan() {
+            0.0
+        } else {
+            n.clamp(f32::MIN, f32::MAX)
+        }
+    };
+
+    let mut table: Vec<f32> = (0..Lut1DHalfDomain::SAMPLES)
+        .map(|bits| {
+            let x = f16_bits_to_f32(bits as u16);
+            if x.is_finite() {
+                finite(f(x))
+            } else {
+                0.0
+            }
+        })
+        .collect();
+
+    for sign in [0, HALF_SIGN] {
+        let y = f(f16_bits_to_f32(HALF_INF | sign));
+        table[(HALF_INF | sign) as usize] = if y.is_finite() {
+            y
+        } else {
+            table[(HALF_MAX_BITS | sign) as usize]
+        };
+    }
+
+    table
+}
+
+/// A 3D lookup table.
+///
+/// `range` specifies the range of the input cube coordinates on all
+/// three axes.  `resolution` specifies the number of samples in each
+/// dimension.
+///
+/// `tables` contains a table for each output component.  Each table has
+/// `resolution[0] * resolution[1] * resolution[2]` elements.  The table
+/// data is laid out such that the following formula can be used to
+/// compute the index of the element at `x,y,z` (or `r,g,b`, etc.):
+///
+/// ```ignore
+/// index = x + (y * resolution[0]) + (z * resolution[0] * resolution[1]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Lut3D {
+    pub range: [(f32, f32); 3],
+    pub resolution: [usize; 3],
+    pub tables: Vec<Vec<f32>>,
+}
+
+impl Lut3D {
+    /// Creates a 3D LUT, checking that it's valid.
+    ///
+    /// See `validate()` for the checks that are done.
+    pub fn new(
+        range: [(f32, f32); 3],
+        resolution: [usize; 3],
+        tables: Vec<Vec<f32>>,
+    ) -> Result<Lut3D, LutError> {
+        let lut = Lut3D {
+            range,
+            resolution,
+            tables,
+        };
+        lut.validate()?;
+        Ok(lut)
+    }
+
+    /// Checks that the LUT is valid.
+    ///
+    /// Specifically, that it has three tables, their lengths match the
+    /// resolution, the resolution is at least 2 on every axis, and the
+    /// ranges are finite and non-empty.
+    pub fn validate(&self) -> Result<(), LutError> {
+        validate_3d(&self.range, self.resolution, &self.tables)
+    }
+
+    pub fn from_fn<F: Fn((f32, f32, f32)) -> (f32, f32, f32)>(
+        resolution: [usize; 3],
+        min: [f32; 3],
+        max: [f32; 3],
+        f: F,
+    ) -> Lut3D {
+        let inc = [
+            (max[0] as f64 - min[0] as f64) / (resolution[0] - 1) as f64,
+            (max[1] as f64 - min[1] as f64) / (resolution[1] - 1) as f64,
+            (max[2] as f64 - min[2] as f64) / (resolution[2] - 1) as f64,
+        ];
+        let mut tables = vec![Vec::new(), Vec::new(), Vec::new()];
+        for zi in 0..resolution[2] {
+            for yi in 0..resolution[1] {
+                for xi in 0..resolution[0] {
+                    let x_in = min[0] + (inc[0] * xi as f64) as f32;
+                    let y_in = min[1] + (inc[1] * yi as f64) as f32;
+                    let z_in = min[2] + (inc[2] * zi as f64) as f32;
+                    let (x, y, z) = f((x_in, y_in, z_in));
+                    tables[0].push(x);
+                    tables[1].push(y);
+                    tables[2].push(z);
+                }
+            }
+        }
+
+        Lut3D {
+            range: [(min[0], max[0]), (min[1], max[1]), (min[2], max[2])],
+            resolution: resolution,
+            tables: tables,
+        }
+    }
+
+    /// Resamples the LUT to a new resolution and/or input range.
+    ///
+    /// New lattice points outside of the LUT's current range are given
+    /// the values at its boundary, since the LUT is clamped rather than
+    /// extrapolated.
+    ///
+    /// Returns an error if the LUT is invalid (see `validate()`), or if
+    /// the new resolution or range is.
+    pub fn resample(
+        &self,
+        resolution: [usize; 3],
+        range: [(f32, f32); 3],
+        interpolation: Interpolation3D,
+    ) -> Result<Lut3D, LutError> {
+        self.validate()?;
+        let sampler = Sampler3D::new(self);
+        bake_3d(resolution, range, |rgb| sampler.look_up(rgb, interpolation))
+    }
+
+    /// Numerically inverts the LUT.
+    ///
+    /// Builds a new LUT with the given `resolution` and input `range`
+    /// that maps the outputs of this LUT back to its inputs.  This LUT is
+    /// treated as a trilinearly interpolated function, and each lattice
+    /// point of the inverse is found by an iterative least-squares search
+    /// over this LUT's input range, seeded from already-solved
+    /// neighboring points.
+    ///
+    /// Lattice points of the inverse whose values this LUT never outputs
+    /// (i.e. that are outside of its gamut) are given the input that gets
+    /// closest to them, and are listed in the returned `unreachable`
+    /// points.  A point counts as reachable if it is matched to within
+    /// `tolerance` on every channel.
+    ///
+    /// The inverse is only meaningful if this LUT is invertible (i.e.
+    /// it doesn't map different inputs to the same output).
+    ///
+    /// Returns an error if the LUT is invalid (see `validate()`), or if
+    /// the inverse's resolution or range is.
+    pub fn invert(
+        &self,
+        resolution: [usize; 3],
+        range: [(f32, f32); 3],
+        tolerance: f32,
+    ) -> Result<Lut3DInverse, LutError> {
+        self.validate()?;
+        let len = validate_lattice(&range, resolution)?;
+
+        let search = InverseSearch::new(self);
+        let inc = [
+            (range[0].1 as f64 - range[0].0 as f64) / (resolution[0] - 1) as f64,
+            (range[1].1 as f64 - range[1].0 as f64) / (resolution[1] - 1) as f64,
+            (range[2].1 as f64 - range[2].0 as f64) / (resolution[2] - 1) as f64,
+        ];
+
+        let mut solutions: Vec<[f32; 3]> = Vec::with_capacity(len);
+        let mut unreachable = Vec::new();
+        for zi in 0..resolution[2] {
+            for yi in 0..resolution[1] {
+                for xi in 0..resolution[0] {
+                    let target = [
+                        range[0].0 + (inc[0] * xi as f64) as f32,
+                        range[1].0 + (inc[1] * yi as f64) as f32,
+                        range[2].0 + (inc[2] * zi as f64) as f32,
+                    ];
+
+                    // Seed from the previously solved neighbors on each
+                    // axis, since the inverse is usually smooth.
+                    let i = solutions.len();
+                    let neighbors = [
+                        (xi > 0).then(|| solutions[i - 1]),
+                        (yi > 0).then(|| solutions[i - resolution[0]]),
+                        (zi > 0).then(|| solutions[i - (resolution[0] * resolution[1])]),
+                    ];
+
+                    let (x, out) = search.solve(target, &neighbors, tolerance);
+                    if (0..3).any(|c| (out[c] - target[c]).abs() > tolerance) {
+                        unreachable.push(UnreachablePoint {
+                            index: [xi, yi, zi],
+                            target,
+                            closest: out,
+                        });
+                    }
+                    solutions.push(x);
+                }
+            }
+        }
+
+        let tables = (0..3)
+            .map(|c| solutions.iter().map(|x| x[c]).collect())
+            .collect();
+
+        Ok(Lut3DInverse {
+            lut: Lut3D {
+                range,
+                resolution,
+                tables,
+            },
+            unreachable,
+        })
+    }
+
+    /// Checks whether the LUT is an identity transform, within
+    /// `tolerance`.
+    ///
+    /// Only the lattice points are checked, so this is exact for
+    /// trilinear and tetrahedral interpolation.  Returns an error if the
+    /// LUT is invalid (see `validate()`).
+    pub fn is_identity(&self, tolerance: f32) -> Result<bool, LutError> {
+        self.validate()?;
+        Ok(self.fits(tolerance, |rgb| rgb))
+    }
+
+    /// Finds the matrix and offset that the LUT is equivalent to, if
+    /// any, within `tolerance`.
+    ///
+    /// The LUT computes `transform_color(rgb, matrix) + offset`.  Only
+    /// the lattice points are checked, so this is exact for trilinear
+    /// and tetrahedral interpolation.  Returns an error if the LUT is
+    /// invalid (see `validate()`).
+    pub fn to_matrix(&self, tolerance: f32) -> Result<Option<(Matrix, [f64; 3])>, LutError> {
+        self.validate()?;
+
+        // Derive the candidate from the corner at the start of the range
+        // and the far corners along each axis, and then check it
+        // against everything else.
+        let last = [
+            self.resolution[0] - 1,
+            self.resolution[1] - 1,
+            self.resolution[2] - 1,
+        ];
+        let origin = self.lattice_value([0, 0, 0]);
+        let mut matrix = [[0.0f64; 3]; 3];
+        for axis in 0..3 {
+            let mut index = [0; 3];
+            index[axis] = last[axis];
+            let end = self.lattice_value(index);
+            let span = self.range[axis].1 as f64 - self.range[axis].0 as f64;
+            for chan in 0..3 {
+                matrix[chan][axis] = (end[chan] as f64 - origin[chan] as f64) / span;
+            }
+        }
+        let start = [
+            self.range[0].0 as f64,
+            self.range[1].0 as f64,
+            self.range[2].0 as f64,
+        ];
+        let start_out = transform_color(start, matrix);
+        let offset = [
+            origin[0] as f64 - start_out[0],
+            origin[1] as f64 - start_out[1],
+            origin[2] as f64 - start_out[2],
+        ];
+
+        let fits = self.fits(tolerance, |rgb| {
+            let out = transform_color([rgb[0] as f64, rgb[1] as f64, rgb[2] as f64], matrix);
+            [
+                (out[0] + offset[0]) as f32,
+                (out[1] + offset[1]) as f32,
+                (out[2] + offset[2]) as f32,
+            ]
+        });
+        if fits {
+            Ok(Some((matrix, offset)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Finds the per-channel 1D LUT that the LUT is equivalent to, if
+    /// any, within `tolerance`.
+    ///
+    /// This is the case when each output channel depends only on the
+    /// corresponding input channel.  The returned LUT has the same
+    /// resolution and input ranges as this one, and three tables.  Only
+    /// the lattice points are checked, so this is exact for trilinear
+    /// and tetrahedral interpolation.  Returns an error if the LUT is
+    /// invalid (see `validate()`).
+    pub fn to_separable(&self, tolerance: f32) -> Result<Option<Lut1D>, LutError> {
+        self.validate()?;
+
+        // Take each curve from the edge of the lattice along its axis,
+        // and then check it against everything else.
+        let tables: Vec<Vec<f32>> = (0..3)
+            .map(|axis| {
+                (0..self.resolution[axis])
+                    .map(|i| {
+                        let mut index = [0; 3];
+                        index[axis] = i;
+                        self.lattice_value(index)[axis]
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for zi in 0..self.resolution[2] {
+            for yi in 0..self.resolution[1] {
+                for xi in 0..self.resolution[0] {
+                    let value = self.lattice_value([xi, yi, zi]);
+                    for (chan, i) in [xi, yi, zi].iter().enumerate() {
+                        if !within(value[chan], tables[chan][*i], tolerance) {
+                            return Ok(None);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(Some(Lut1D {
+            ranges: self.range.to_vec(),
+            tables,
+        }))
+    }
+
+    /// Finds the simplest equivalent representation of the LUT, if
+    /// there is one simpler than a 3D LUT, within `tolerance`.
+    ///
+    /// See `is_identity()`, `to_matrix()`, and `to_separable()`, which
+    /// are tried in that order.  Returns an error if the LUT is invalid
+    /// (see `validate()`).
+    pub fn simplify(&self, tolerance: f32) -> Result<Option<SimplifiedLut3D>, LutError> {
+        Ok(if self.is_identity(tolerance)? {
+            Some(SimplifiedLut3D::Identity)
+        } else if let Some((matrix, offset)) = self.to_matrix(tolerance)? {
+            Some(SimplifiedLut3D::Matrix(matrix, offset))
+        } else {
+            self.to_separable(tolerance)?
+                .map(SimplifiedLut3D::Separable)
+        })
+    }
+
+    /// Returns a smoothed copy of the LUT, e.g. for reducing noise in
+    /// LUTs generated from measurements.
+    ///
+    /// This applies a Gaussian blur in lattice space, where `strength`
+    /// is the standard deviation in lattice cells.  A `strength` of zero
+    /// returns the LUT unchanged, and the blur's reach is limited to the
+    /// size of the lattice, however large `strength` is.  At the edges of the lattice the data
+    /// is extrapolated linearly, so that the blur doesn't pull the
+    /// outer lattice points inward, and linear data passes through
+    /// unchanged.
+    ///
+    /// The corners of the lattice and the lattice points on the neutral
+    /// axis (where all three input channels are equal) are pinned to
+    /// their original values.
+    ///
+    /// Returns an error if the LUT is invalid (see `validate()`).
+    pub fn smoothed(&self, strength: f32) -> Result<Lut3D, LutError> {
+        self.validate()?;
+
+        let mut lut = self.clone();
+        if strength.is_nan() || strength <= 0.0 {
+            return Ok(lut);
+        }
+
+        // The kernel never needs to reach past the whole lattice, which
+        // also keeps huge strengths from allocating huge kernels.
+        let res = self.resolution;
+        let kernel = gaussian_kernel(strength as f64, res.into_iter().max().unwrap());
+        let strides = [1, res[0], res[0] * res[1]];
+        let mut line = Vec::new();
+        let mut smoothed = Vec::new();
+        for axis in 0..3 {
+            let a = (axis + 1) % 3;
+            let b = (axis + 2) % 3;
+            for table in lut.tables.iter_mut() {
+                for j in 0..res[b] {
+                    for i in 0..res[a] {
+                        let start = i * strides[a] + j * strides[b];
+                        line.clear();
+                        line.extend((0..res[axis]).map(|k| table[start + k * strides[axis]]));
+                        smooth_line(&line, &kernel, &mut smoothed);
+                        for (k, n) in smoothed.iter().enumerate() {
+                            table[start + k * strides[axis]] = *n;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Restore the pinned points.
+        let inc = [0, 1, 2].map(|axis| {
+            (self.range[axis].1 as f64 - self.range[axis].0 as f64) / (res[axis] - 1) as f64
+        });
+        let input = |axis: usize, i: usize| self.range[axis].0 + (inc[axis] * i as f64) as f32;
+        for zi in 0..res[2] {
+            for yi in 0..res[1] {
+                for xi in 0..res[0] {
+                    let index = [xi, yi, zi];
+                    let is_corner =
+                        (0..3).all(|axis| index[axis] == 0 || index[axis] == res[axis] - 1);
+                    let is_neutral = input(0, xi) == input(1, yi) && input(1, yi) == input(2, zi);
+                    if is_corner || is_neutral {
+                        let i = xi + (yi * strides[1]) + (zi * strides[2]);
+                        for (table, original) in lut.tables.iter_mut().zip(self.tables.iter()) {
+                            table[i] = original[i];
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(lut)
+    }
+
+    /// Returns the output value at the given lattice point.
+    fn lattice_value(&self, index: [usize; 3]) -> [f32; 3] {
+        let i = index[0]
+            + (index[1] * self.resolution[0])
+            + (index[2] * self.resolution[0] * self.resolution[1]);
+        [self.tables[0][i], self.tables[1][i], self.tables[2][i]]
+    }
+
+    /// Checks whether all lattice points match `f` within `tolerance`.
+    fn fits<F: Fn([f32; 3]) -> [f32; 3]>(&self, tolerance: f32, f: F) -> bool {
+        let inc = [0, 1, 2].map(|axis| {
+            (self.range[axis].1 as f64 - self.range[axis].0 as f64)
+                / (self.resolution[axis] - 1) as f64
+        });
+        let input = |axis: usize, i: usize| self.range[axis].0 + (inc[axis] * i as f64) as f32;
+
+        for zi in 0..self.resolution[2] {
+            for yi in 0..self.resolution[1] {
+                for xi in 0..self.resolution[0] {
+                    let expected = f([input(0, xi), input(1, yi), input(2, zi)]);
+                    let value = self.lattice_value([xi, yi, zi]);
+                    if (0..3).any(|c| !within(value[c], expected[c], tolerance)) {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Convenience function for doing a trilinearly interpolated lookup.
+    ///
+    /// Inputs outside of `range` are clamped to it.
+    ///
+    /// Note: this is a convenience function, and is not intended for high-performance
+    /// situations.  See `apply()` for processing many values.
+    pub fn look_up_trilinear(&self, rgb: [f32; 3]) -> [f32; 3] {
+        Sampler3D::new(self).trilinear(rgb)
+    }
+
+    /// Convenience function for doing a tetrahedrally interpolated lookup.
+    ///
+    /// Inputs outside of `range` are clamped to it.
+    ///
+    /// Tetrahedral interpolation is generally preferred for color LUTs,
+    /// since it interpolates the neutral axis (the diagonal of each cell)
+    /// using only the two neutral lattice points.
+    ///
+    /// Note: this is a convenience function, and is not intended for high-performance
+    /// situations.  See `apply()` for processing many values.
+    pub fn look_up_tetrahedral(&self, rgb: [f32; 3]) -> [f32; 3] {
+        Sampler3D::new(self).tetrahedral(rgb)
+    }
+
+    /// Applies the LUT to a buffer of RGB values in place.
+    ///
+    /// Inputs outside of `range` are clamped to it.
+    pub fn apply(&self, pixels: &mut [[f32; 3]], interpolation: Interpolation3D) {
+        let sampler = Sampler3D::new(self);
+        match interpolation {
+            Interpolation3D::Trilinear => {
+                for rgb in pixels.iter_mut() {
+                    *rgb = sampler.trilinear(*rgb);
+                }
+            }
+            Interpolation3D::Tetrahedral => {
+                for rgb in pixels.iter_mut() {
+                    *rgb = sampler.tetrahedral(*rgb);
+                }
+            }
+        }
+    }
+
+    /// Applies the LUT in place to a buffer of interleaved pixels with
+    /// `channels` channels each, e.g. 3 for RGB or 4 for RGBA.
+    ///
+    /// The first three channels of each pixel are treated as RGB, and any
+    /// further channels (e.g. alpha) are left untouched.  Inputs outside
+    /// of `range` are clamped to it.
+    pub fn apply_interleaved(
+        &self,
+        data: &mut [f32],
+        channels: usize,
+        interpolation: Interpolation3D,
+    ) {
+        let sampler = Sampler3D::new(self);
+        match interpolation {
+            Interpolation3D::Trilinear => {
+                for_each_rgb(data, channels, |rgb| sampler.trilinear(rgb))
+            }
+            Interpolation3D::Tetrahedral => {
+                for_each_rgb(data, channels, |rgb| sampler.tetrahedral(rgb))
+            }
+        }
+    }
+}
+
+/// Builds a normalized Gaussian kernel with standard deviation `sigma`,
+/// truncated at three standard deviations or `max_radius`, whichever is
+/// smaller.
+fn gaussian_kernel(sigma: f64, max_radius: usize) -> Vec<f64> {
+    let radius = (sigma * 3.0).ceil().min(max_radius as f64) as isize;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|x| (-((x * x) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.iter().map(|n| n / sum).collect()
+}
+
+/// Convolves `line` with `kernel`, writing the result to `out`.
+///
+/// Samples past the ends are extrapolated from a least-squares line
+/// through the samples nearest each end, which leaves linear ramps
+/// unchanged without leaving the end samples themselves unsmoothed.
+fn smooth_line(line: &[f32], kernel: &[f64], out: &mut Vec<f32>) {
+    let last = line.len() as isize - 1;
+    let radius = (kernel.len() / 2) as isize;
+
+    // Fits `a + b * i` to the samples at `indices`.
+    let fit = |indices: &mut dyn Iterator<Item = isize>| -> (f64, f64) {
+        let (mut n, mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for i in indices {
+            let (x, y) = (i as f64, line[i as usize] as f64);
+            n += 1.0;
+            sx += x;
+            sy += y;
+            sxx += x * x;
+            sxy += x * y;
+        }
+        let b = (n * sxy - sx * sy) / (n * sxx - sx * sx);
+        ((sy - b * sx) / n, b)
+    };
+    let span = radius.clamp(1, last);
+    let start = fit(&mut (0..=span));
+    let end = fit(&mut (last - span..=last));
+
+    let sample = |p: isize| -> f64 {
+        if p < 0 {
+            start.0 + start.1 * p as f64
+        } else if p > last {
+            end.0 + end.1 * p as f64
+        } else {
+            line[p as usize] as f64
+        }
+    };
+
+    out.clear();
+    out.extend((0..=last).map(|k| {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(o, w)| w * sample(k + o as isize - radius))
+            .sum::<f64>() as f32
+    }));
+}
+
+impl Default for Lut3D {
+    fn default() -> Lut3D {
+        Lut3D {
+            range: [(0.0, 1.0); 3],
+            resolution: [0; 3],
+            tables: Vec::new(),
+        }
+    }
+}
+
+/// The result of `Lut3D::invert()`.
+#[derive(Debug, Clone)]
+pub struct Lut3DInverse {
+    /// The inverse LUT.
+    pub lut: Lut3D,
+
+    /// The lattice points of the inverse LUT that the forward LUT
+    /// can't reach.
+    ///
+    /// These points are given the input of the forward LUT whose output
+    /// is closest to them in the least-squares sense, which effectively
+    /// gamut-clips them.
+    pub unreachable: Vec<UnreachablePoint>,
+}
+
+/// A lattice point of an inverse LUT that the forward LUT can't reach.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UnreachablePoint {
+    /// The lattice indices of the point in the inverse LUT.
+    pub index: [usize; 3],
+    /// The input value of the point in the inverse LUT, which is the
+    /// output value that was searched for in the forward LUT.
+    pub target: [f32; 3],
+    /// The closest output value that the forward LUT produces, which is
+    /// what the point's value in the inverse LUT maps to.
+    pub closest: [f32; 3],
+}
+
+/// Iterative least-squares search for the inputs of a 3D LUT that
+/// produce given outputs.
+struct InverseSearch<'a> {
+    sampler: Sampler3D<'a>,
+    range: [(f32, f32); 3],
+    /// Step sizes for estimating derivatives on each axis.
+    step: [f32; 3],
+    /// A coarse subset of the forward LUT's lattice, as (input, output)
+    /// pairs, for seeding searches when no neighbor solution works.
+    coarse: Vec<([f32; 3], [f32; 3])>,
+}
+
+impl<'a> InverseSearch<'a> {
+    /// The maximum number of lattice points per axis in `coarse`.
+    const COARSE_RES: usize = 9;
+    const MAX_ITERATIONS: usize = 50;
+
+    fn new(lut: &'a Lut3D) -> InverseSearch<'a> {
+        let sampler = Sampler3D::new(lut);
+        let range = lut.range;
+        let mut step = [0.0f32; 3];
+        for axis in 0..3 {
+            // A small fraction of a lattice cell, so the estimates stay
+            // within one cell of the piecewise function.
+            step[axis] = (range[axis].1 - range[axis].0) / (lut.resolution[axis] - 1) as f32 * 0.01;
+        }
+
+        let coarse_res = [
+            lut.resolution[0].min(Self::COARSE_RES),
+            lut.resolution[1].min(Self::COARSE_RES),
+            lut.resolution[2].min(Self::COARSE_RES),
+        ];
+        let coarse_lut = Lut3D::from_fn(
+            coarse_res,
+            [range[0].0, range[1].0, range[2].0],
+            [range[0].1, range[1].1, range[2].1],
+            |(r, g, b)| (r, g, b),
+        );
+        let coarse = (0..coarse_lut.tables[0].len())
+            .map(|i| {
+                let x = [
+                    coarse_lut.tables[0][i],
+                    coarse_lut.tables[1][i],
+                    coarse_lut.tables[2][i],
+                ];
+                (x, sampler.trilinear(x))
+            })
+            .collect();
+
+        InverseSearch {
+            sampler,
+            range,
+            step,
+            coarse,
+        }
+    }
+
+    /// Finds the input that produces the output closest to `target`.
+    ///
+    /// Tries each of the `seeds` in turn, falling back to the nearest
+    /// coarse lattice point if none of them get within `tolerance`.
+    /// Returns the best input found and its output.
+    fn solve(
+        &self,
+        target: [f32; 3],
+        seeds: &[Option<[f32; 3]>],
+        tolerance: f32,
+    ) -> ([f32; 3], [f32; 3]) {
+        let within_tolerance =
+            |out: [f32; 3]| (0..3).all(|c| (out[c] - target[c]).abs() <= tolerance);
+
+        let mut best: Option<([f32; 3], [f32; 3], f64)> = None;
+        for seed in seeds.iter().flatten() {
+            let (x, out, err) = self.refine(target, *seed, tolerance);
+            if within_tolerance(out) {
+                return (x, out);
+            }
+            if best.map(|b| err < b.2).unwrap_or(true) {
+                best = Some((x, out, err));
+            }
+        }
+
+        let seed = self
+            .coarse
+            .iter()
+            .min_by(|a, b| sq_dist(a.1, target).total_cmp(&sq_dist(b.1, target)))
+            .unwrap()
+            .0;
+        let (x, out, err) = self.refine(target, seed, tolerance);
+        match best {
+            Some(b) if b.2 < err => (b.0, b.1),
+            _ => (x, out),
+        }
+    }
+
+    /// Refines an input with the Levenberg-Marquardt method, keeping it
+    /// within the LUT's input range.
+    ///
+    /// Returns the refined input, its output, and its squared error.
+    fn refine(
+        &self,
+        target: [f32; 3],
+        seed: [f32; 3],
+        tolerance: f32,
+    ) -> ([f32; 3], [f32; 3], f64) {
+        let mut x = self.clamp(seed);
+        let mut out = self.sampler.trilinear(x);
+        let mut err = sq_dist(out, target);
+        let mut damping = 1.0e-3;
+
+        for _ in 0..Self::MAX_ITERATIONS {
+            if (0..3).all(|c| (out[c] - target[c]).abs() <= tolerance * 0.5) {
+                break;
+            }
+
+            // Estimate the Jacobian, using one-sided differences that
+            // stay within the input range.
+            let mut jacobian = [[0.0f64; 3]; 3];
+            for axis in 0..3 {
+                let mut x2 = x;
+                let mut h = self.step[axis];
+                if x[axis] + h > self.range[axis].1 {
+                    h = -h;
+                }
+                x2[axis] += h;
+                let out2 = self.sampler.trilinear(x2);
+                for c in 0..3 {
+                    jacobian[c][axis] = (out2[c] as f64 - out[c] as f64) / h as f64;
+                }
+            }
+
+            // Normal equations: (J^T J) dx = J^T r
+            let residual = [
+                target[0] as f64 - out[0] as f64,
+                target[1] as f64 - out[1] as f64,
+                target[2] as f64 - out[2] as f64,
+            ];
+            let mut jtj = [[0.0f64; 3]; 3];
+            let mut jtr = [0.0f64; 3];
+            for i in 0..3 {
+                for j in 0..3 {
+                    jtj[i][j] = (0..3).map(|c| jacobian[c][i] * jacobian[c][j]).sum();
+                }
+                jtr[i] = (0..3).map(|c| jacobian[c][i] * residual[c]).sum();
+            }
+
+            // Find a damping amount that reduces the error.
+            let mut improved = false;
+            while damping < 1.0e10 {
+                let mut m = jtj;
+                for i in 0..3 {
+                    m[i][i] += damping * (jtj[i][i] + 1.0e-12);
+                }
+                if let Some(m_inv) = crate::matrix::inverse(m) {
+                    let dx = crate::matrix::transform_color(jtr, m_inv);
+                    let x2 = self.clamp([
+                        x[0] + dx[0] as f32,
+                        x[1] + dx[1] as f32,
+                        x[2] + dx[2] as f32,
+                    ]);
+                    let out2 = self.sampler.trilinear(x2);
+                    let err2 = sq_dist(out2, target);
+                    if err2 < err {
+                        x = x2;
+                        out = out2;
+                        err = err2;
+                        damping = (damping * 0.1).max(1.0e-9);
+                        improved = true;
+                        break;
+                    }
+                }
+                damping *= 10.0;
+            }
+            if !improved {
+                // Converged as far as we can get, e.g. to the closest
+                // point of an unreachable target.
+                break;
+            }
+        }
+
+        (x, out, err)
+    }
+
+    fn clamp(&self, x: [f32; 3]) -> [f32; 3] {
+        [
+            x[0].clamp(self.range[0].0, self.range[0].1),
+            x[1].clamp(self.range[1].0, self.range[1].1),
+            x[2].clamp(self.range[2].0, self.range[2].1),
+        ]
+    }
+}
+
+fn sq_dist(a: [f32; 3], b: [f32; 3]) -> f64 {
+    (0..3)
+        .map(|i| {
+            let d = a[i] as f64 - b[i] as f64;
+            d * d
+        })
+        .sum()
+}
+
+/// A simpler equivalent of a 3D LUT, as found by `Lut3D::simplify()`.
+#[derive(Debug, Clone)]
+pub enum SimplifiedLut3D {
+    Identity,
+    /// A matrix, and an offset that is added after the matrix.
+    Matrix(Matrix, [f64; 3]),
+    /// Independent per-channel curves.
+    Separable(Lut1D),
+}
+
+/// Whether `a` and `b` differ by no more than `tolerance`.  NaNs are
+/// never within tolerance.
+fn within(a: f32, b: f32, tolerance: f32) -> bool {
+    (a - b).abs() <= tolerance
+}
+
+/// Interpolation methods for 3D LUTs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation3D {
+    Trilinear,
+    /// Generally preferred for color LUTs, since it interpolates the
+    /// neutral axis using only neutral lattice points.
+    Tetrahedral,
+}
+
+/// Interpolation methods for 1D LUTs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation1D {
+    Linear,
+    /// Smooth, but can overshoot between samples, so monotonic tables
+    /// don't necessarily stay monotonic.
+    CatmullRom,
+    /// Monotone cubic Hermite interpolation (Fritsch-Carlson).  Smooth,
+    /// and never overshoots, so monotonic tables stay monotonic.
+    MonotoneCubic,
+}
+
+/// Samples `table` at the fractional index `x`, which is clamped to the
+/// table's bounds.
+fn sample_table(table: &[f32], x: f32, interpolation: Interpolation1D) -> f32 {
+    let last = table.len() - 1;
+    let x = x.clamp(0.0, last as f32);
+
+    // The top end uses the last interval, with an alpha of 1.0.
+    let i = (x as usize).min(last - 1);
+    let t = x - i as f32;
+    let (p0, p1) = (table[i], table[i + 1]);
+
+    let (m0, m1) = match interpolation {
+        Interpolation1D::Linear => return p0 + ((p1 - p0) * t),
+        Interpolation1D::CatmullRom => (
+            catmull_rom_tangent(table, i),
+            catmull_rom_tangent(table, i + 1),
+        ),
+        Interpolation1D::MonotoneCubic => {
+            (monotone_tangent(table, i), monotone_tangent(table, i + 1))
+        }
+    };
+
+    // Cubic Hermite basis.
+    let t2 = t * t;
+    let t3 = t2 * t;
+    let h00 = (2.0 * t3) - (3.0 * t2) + 1.0;
+    let h10 = t3 - (2.0 * t2) + t;
+    let h01 = (-2.0 * t3) + (3.0 * t2);
+    let h11 = t3 - t2;
+    (h00 * p0) + (h10 * m0) + (h01 * p1) + (h11 * m1)
+}
+
+/// The Catmull-Rom tangent at index `i`, in units of table indices.
+///
+/// The ends use one-sided differences.
+fn catmull_rom_tangent(table: &[f32], i: usize) -> f32 {
+    let last = table.len() - 1;
+    if i == 0 {
+        table[1] - table[0]
+    } else if i == last {
+        table[last] - table[last - 1]
+    } else {
+        (table[i + 1] - table[i - 1]) * 0.5
+    }
+}
+
+/// The Fritsch-Carlson monotone tangent at index `i`, in units of table
+/// indices.
+///
+/// This only depends on the neighboring samples, so it can be computed
+/// on demand rather than for the whole table up front.
+fn monotone_tangent(table: &[f32], i: usize) -> f32 {
+    let last = table.len() - 1;
+
+    // Secant of the interval starting at `k`.
+    let secant = |k: usize| table[k + 1] - table[k];
+
+    // Initial tangent: the mean of the adjacent secants, or zero at local
+    // extrema.
+    let initial = |k: usize| {
+        if k == 0 {
+            secant(0)
+        } else if k == last {
+            secant(last - 1)
+        } else {
+            let (d0, d1) = (secant(k - 1), secant(k));
+            if (d0 * d1) <= 0.0 {
+                0.0
+            } else {
+                (d0 + d1) * 0.5
+            }
+        }
+    };
+
+    // Limit the tangent so that neither adjacent interval overshoots.
+    let mut scale = 1.0f32;
+    let intervals = [i.checked_sub(1), if i < last { Some(i) } else { None }];
+    for k in intervals.iter().flatten().copied() {
+        let d = secant(k);
+        if d == 0.0 {
+            return 0.0;
+        }
+        let a = initial(k) / d;
+        let b = initial(k + 1) / d;
+        let s = (a * a) + (b * b);
+        if s > 9.0 {
+            scale = scale.min(3.0 / s.sqrt());
+        }
+    }
+
+    initial(i) * scale
+}
+
+/// Calls `f` on the first three channels of each pixel of an interleaved
+/// buffer, replacing them with the result.
+fn for_each_rgb<F: Fn([f32; 3]) -> [f32; 3]>(data: &mut [f32], channels: usize, f: F) {
+    assert!(channels >= 3);
+    assert_eq!(data.len() % channels, 0);
+
+    for pixel in data.chunks_exact_mut(channels) {
+        let rgb = f([pixel[0], pixel[1], pixel[2]]);
+        pixel[..3].copy_from_slice(&rgb);
+    }
+}
+
+/// Precomputed data for fast 1D LUT lookups.
+///
+/// All validation is done up front in `new()`, so that the per-value
+/// lookups don't need to do any.
+struct Sampler1D<'a> {
+    tables: [&'a [f32]; 3],
+    /// The start of each channel's input range.
+    offset: [f32; 3],
+    /// Maps each channel's input range to its table indices.
+    scale: [f32; 3],
+}
+
+impl<'a> Sampler1D<'a> {
+    fn new(lut: &'a Lut1D) -> Sampler1D<'a> {
+        assert!(lut.tables.len() == 1 || lut.tables.len() == 3);
+        assert!(lut.ranges.len() == 1 || lut.ranges.len() == lut.tables.len());
+        assert!(lut.tables.iter().all(|t| t.len() >= 2));
+
+        let mut sampler = Sampler1D {
+            tables: [&[]; 3],
+            offset: [0.0; 3],
+            scale: [0.0; 3],
+        };
+        for chan in 0..3 {
+            let table = &lut.tables[chan.min(lut.tables.len() - 1)][..];
+            let range = lut.ranges[chan.min(lut.ranges.len() - 1)];
+            sampler.tables[chan] = table;
+            sampler.offset[chan] = range.0;
+            sampler.scale[chan] = (table.len() - 1) as f32 / (range.1 - range.0);
+        }
+
+        sampler
+    }
+
+    #[inline]
+    fn look_up(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let mut out = [0.0f32; 3];
+        for chan in 0..3 {
+            let table = self.tables[chan];
+            let last = table.len() - 1;
+            let x = ((rgb[chan] - self.offset[chan]) * self.scale[chan]).clamp(0.0, last as f32);
+
+            // The top end uses the last interval, with an alpha of 1.0.
+            let i = (x as usize).min(last - 1);
+            let alpha = x - i as f32;
+            out[chan] = table[i] + ((table[i + 1] - table[i]) * alpha);
+        }
+        out
+    }
+}
+
+/// Precomputed data for fast 3D LUT lookups.
+///
+/// All validation is done up front in `new()`, so that the per-value
+/// lookups don't need to do any.
+struct Sampler3D<'a> {
+    tables: [&'a [f32]; 3],
+    /// The start of the input range on each axis.
+    offset: [f32; 3],
+    /// Maps the input range on each axis to lattice coordinates.
+    scale: [f32; 3],
+    /// The index of the last lattice cell on each axis.
+    last_cell: [usize; 3],
+    /// The table index offsets between adjacent lattice points on each
+    /// axis.
+    strides: [usize; 3],
+}
+
+impl<'a> Sampler3D<'a> {
+    fn new(lut: &'a Lut3D) -> Sampler3D<'a> {
+        let res = lut.resolution;
+        assert!(lut.tables.len() == 3);
+        assert!(res.iter().all(|r| *r >= 2));
+        assert!(lut
+            .tables
+            .iter()
+            .all(|t| t.len() == res[0] * res[1] * res[2]));
+
+        let range = lut.range;
+        Sampler3D {
+            tables: [&lut.tables[0], &lut.tables[1], &lut.tables[2]],
+            offset: [range[0].0, range[1].0, range[2].0],
+            scale: [
+                (res[0] - 1) as f32 / (range[0].1 - range[0].0),
+                (res[1] - 1) as f32 / (range[1].1 - range[1].0),
+                (res[2] - 1) as f32 / (range[2].1 - range[2].0),
+            ],
+            last_cell: [res[0] - 2, res[1] - 2, res[2] - 2],
+            strides: [1, res[0], res[0] * res[1]],
+        }
+    }
+
+    /// Finds the lattice cell that contains a point.
+    ///
+    /// Returns the table index of the cell's lowest corner and the
+    /// point's fractional position within the cell on each axis.
+    #[inline]
+    fn cell(&self, rgb: [f32; 3]) -> (usize, [f32; 3]) {
+        let mut index = 0;
+        let mut fract = [0.0f32; 3];
+        for axis in 0..3 {
+            let last = (self.last_cell[axis] + 1) as f32;
+            let x = ((rgb[axis] - self.offset[axis]) * self.scale[axis]).clamp(0.0, last);
+
+            // The top edge uses the last cell, with a fraction of 1.0.
+            let i = (x as usize).min(self.last_cell[axis]);
+            index += i * self.strides[axis];
+            fract[axis] = x - i as f32;
+        }
+
+        (index, fract)
+    }
+
+    #[inline]
+    fn look_up(&self, rgb: [f32; 3], interpolation: Interpolation3D) -> [f32; 3] {
+        match interpolation {
+            Interpolation3D::Trilinear => self.trilinear(rgb),
+            Interpolation3D::Tetrahedral => self.tetrahedral(rgb),
+        }
+    }
+
+    #[inline]
+    fn trilinear(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let (i, [fx, fy, fz]) = self.cell(rgb);
+        let [sx, sy, sz] = self.strides;
+
+        let lerp = |a: f32, b: f32, alpha: f32| a + ((b - a) * alpha);
+        let mut out = [0.0f32; 3];
+        for (o, table) in out.iter_mut().zip(self.tables.iter()) {
+            let c00 = lerp(table[i], table[i + sx], fx);
+            let c10 = lerp(table[i + sy], table[i + sy + sx], fx);
+            let c01 = lerp(table[i + sz], table[i + sz + sx], fx);
+            let c11 = lerp(table[i + sz + sy], table[i + sz + sy + sx], fx);
+            *o = lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz);
+        }
+        out
+    }
+
+    #[inline]
+    fn tetrahedral(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let (i, [fx, fy, fz]) = self.cell(rgb);
+        let [sx, sy, sz] = self.strides;
+
+        // Pick the tetrahedron containing the point, as the path of
+        // lattice offsets from the cell's first corner to its last.
+        let (weights, offsets) = if fx > fy {
+            if fy > fz {
+                ([fx, fy, fz], [sx, sy, sz])
+            } else if fx > fz {
+                ([fx, fz, fy], [sx, sz, sy])
+            } else {
+                ([fz, fx, fy], [sz, sx, sy])
+            }
+        } else if fz > fy {
+            ([fz, fy, fx], [sz, sy, sx])
+        } else if fz > fx {
+            ([fy, fz, fx], [sy, sz, sx])
+        } else {
+            ([fy, fx, fz], [sy, sx, sz])
+        };
+        let i1 = i + offsets[0];
+        let i2 = i1 + offsets[1];
+        let i3 = i2 + offsets[2];
+
+        let mut out = [0.0f32; 3];
+        for (o, table) in out.iter_mut().zip(self.tables.iter()) {
+            *o = table[i]
+                + ((table[i1] - table[i]) * weights[0])
+                + ((table[i2] - table[i1]) * weights[1])
+                + ((table[i3] - table[i2]) * weights[2]);
+        }
+        out
+    }
+}
+
+/// Composes two 1D LUTs into a single 1D LUT.
+///
+/// The new LUT has `samples` samples over the input ranges of `first`,
+/// and computes `first` followed by `second`.  Each LUT must have either
+/// one table or three, and the new LUT has three tables if either of
+/// them does.
+///
+/// Returns an error if either LUT is invalid, or if `samples` is less
+/// than 2.
+pub fn compose_1d_1d(first: &Lut1D, second: &Lut1D, samples: usize) -> Result<Lut1D, LutError> {
+    validate_1d_rgb(first)?;
+    validate_1d_rgb(second)?;
+    if samples < 2 {
+        return Err(LutError::TableTooShort {
+            index: 0,
+            len: samples,
+        });
+    }
+    let first_sampler = Sampler1D::new(first);
+    let second_sampler = Sampler1D::new(second);
+    let channels = first.tables.len().max(second.tables.len());
+
+    let mut tables = vec![Vec::with_capacity(samples); channels];
+    let ranges: Vec<(f32, f32)> = (0..channels)
+        .map(|c| first.ranges[c.min(first.ranges.len() - 1)])
+        .collect();
+    for i in 0..samples {
+        let t = i as f64 / (samples - 1) as f64;
+        let mut rgb = [0.0f32; 3];
+        for (c, n) in rgb.iter_mut().enumerate() {
+            let range = ranges[c.min(channels - 1)];
+            *n = range.0 + ((range.1 as f64 - range.0 as f64) * t) as f32;
+        }
+        let out = second_sampler.look_up(first_sampler.look_up(rgb));
+        for (table, n) in tables.iter_mut().zip(out.iter()) {
+            table.push(*n);
+        }
+    }
+
+    // All ranges are the same when there's only one table.
+    let ranges = if channels == 1 {
+        vec![ranges[0]]
+    } else {
+        ranges
+    };
+
+    Ok(Lut1D { ranges, tables })
+}
+
+/// Composes a 1D LUT followed by a 3D LUT into a single 3D LUT.
+///
+/// The new LUT has the given `resolution` and input `range`, and
+/// `second` is evaluated with `interpolation`.  `first` must have either
+/// one table or three.
+///
+/// Returns an error if either LUT is invalid, or if the new resolution
+/// or range is.
+pub fn compose_1d_3d(
+    first: &Lut1D,
+    second: &Lut3D,
+    resolution: [usize; 3],
+    range: [(f32, f32); 3],
+    interpolation: Interpolation3D,
+) -> Result<Lut3D, LutError> {
+    validate_1d_rgb(first)?;
+    second.validate()?;
+    let first_sampler = Sampler1D::new(first);
+    let second_sampler = Sampler3D::new(second);
+    bake_3d(resolution, range, |rgb| {
+        second_sampler.look_up(first_sampler.look_up(rgb), interpolation)
+    })
+}
+
+/// Composes a 3D LUT followed by a 1D LUT into a single 3D LUT.
+///
+/// The new LUT has the given `resolution` and input `range`, and
+/// `first` is evaluated with `interpolation`.  `second` must have either
+/// one table or three.
+///
+/// Returns an error if either LUT is invalid, or if the new resolution
+/// or range is.
+pub fn compose_3d_1d(
+    first: &Lut3D,
+    second: &Lut1D,
+    resolution: [usize; 3],
+    range: [(f32, f32); 3],
+    interpolation: Interpolation3D,
+) -> Result<Lut3D, LutError> {
+    first.validate()?;
+    validate_1d_rgb(second)?;
+    let first_sampler = Sampler3D::new(first);
+    let second_sampler = Sampler1D::new(second);
+    bake_3d(resolution, range, |rgb| {
+        second_sampler.look_up(first_sampler.look_up(rgb, interpolation))
+    })
+}
+
+/// Composes two 3D LUTs into a single 3D LUT.
+///
+/// The new LUT has the given `resolution` and input `range`, and both
+/// LUTs are evaluated with `interpolation`.
+///
+/// Returns an error if either LUT is invalid, or if the new resolution
+/// or range is.
+pub fn compose_3d_3d(
+    first: &Lut3D,
+    second: &Lut3D,
+    resolution: [usize; 3],
+    range: [(f32, f32); 3],
+    interpolation: Interpolation3D,
+) -> Result<Lut3D, LutError> {
+    first.validate()?;
+    second.validate()?;
+    let first_sampler = Sampler3D::new(first);
+    let second_sampler = Sampler3D::new(second);
+    bake_3d(resolution, range, |rgb| {
+        second_sampler.look_up(first_sampler.look_up(rgb, interpolation), interpolation)
+    })
+}
+
+/// Builds a 3D LUT by sampling a function over the given input range.
+///
+/// Returns an error if the resolution or range is invalid.
+fn bake_3d<F: Fn([f32; 3]) -> [f32; 3]>(
+    resolution: [usize; 3],
+    range: [(f32, f32); 3],
+    f: F,
+) -> Result<Lut3D, LutError> {
+    validate_lattice(&range, resolution)?;
+    let min = [range[0].0, range[1].0, range[2].0];
+    let max = [range[0].1, range[1].1, range[2].1];
+    Ok(Lut3D::from_fn(resolution, min, max, |(r, g, b)| {
+        let [r, g, b] = f([r, g, b]);
+        (r, g, b)
+    }))
+}
+
+/// A shaper curve for `bake_shaped()`.
+///
+/// Shapers are normalized so that the linear input range passed to
+/// `bake_shaped()` maps to `[0.0, 1.0]`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shaper {
+    /// `log2(x + offset)`.
+    ///
+    /// The offset keeps the curve defined at the bottom of the input
+    /// range, and in effect determines how many stops below the top of
+    /// the range get a reasonable share of the samples.
+    Log2 { offset: f32 },
+    /// Rec.2100 PQ, with the top of the input range mapped to
+    /// `LUMINANCE_MAX`.
+    Pq,
+    /// DaVinci Intermediate, with the top of the input range mapped to
+    /// its `LINEAR_MAX`.
+    DavinciIntermediate,
+}
+
+impl Shaper {
+    /// Returns the shapers that `bake_shaped()` chooses from.
+    fn candidates(range: (f32, f32)) -> Vec<Shaper> {
+        let mut shapers: Vec<Shaper> = [8, 12, 16, 20]
+            .iter()
+            .map(|stops| Shaper::Log2 {
+                offset: ((range.1 as f64 - range.0 as f64) / (1u32 << stops) as f64
+                    - range.0 as f64) as f32,
+            })
+            .collect();
+        shapers.push(Shaper::Pq);
+        shapers.push(Shaper::DavinciIntermediate);
+        shapers
+    }
+
+    /// Returns the normalized form of the shaper for the given range.
+    fn normalized(self, range: (f32, f32)) -> NormalizedShaper {
+        assert!(range.0 < range.1 && range.1 > 0.0);
+
+        let scale = match self {
+            Shaper::Log2 { offset } => {
+                assert!(range.0 + offset > 0.0);
+                1.0
+            }
+            Shaper::Pq => crate::transfer_functions::rec2100_pq::LUMINANCE_MAX / range.1,
+            Shaper::DavinciIntermediate => {
+                crate::transfer_functions::blackmagic::davinci_intermediate::LINEAR_MAX / range.1
+            }
+        };
+
+        let mut shaper = NormalizedShaper {
+            shaper: self,
+            scale,
+            low: 0.0,
+            norm: 1.0,
+        };
+        let low = shaper.encode(range.0);
+        let high = shaper.encode(range.1);
+        shaper.low = low;
+        shaper.norm = 1.0 / (high - low);
+        shaper
+    }
+}
+
+/// A `Shaper` scaled and offset to map a specific input range to
+/// `[0.0, 1.0]`.
+struct NormalizedShaper {
+    shaper: Shaper,
+    /// Input scale factor, applied before the curve.
+    scale: f32,
+    /// Curve value of the bottom of the input range.
+    low: f32,
+    /// Reciprocal of the curve's output span over the input range.
+    norm: f32,
+}
+
+impl NormalizedShaper {
+    /// Linear -> `[0.0, 1.0]`.
+    fn shape(&self, x: f32) -> f32 {
+        (self.encode(x) - self.low) * self.norm
+    }
+
+    /// `[0.0, 1.0]` -> linear.
+    fn unshape(&self, n: f32) -> f32 {
+        self.decode((n / self.norm) + self.low)
+    }
+
+    fn encode(&self, x: f32) -> f32 {
+        use crate::transfer_functions::{blackmagic::davinci_intermediate, rec2100_pq};
+        let x = x * self.scale;
+        match self.shaper {
+            Shaper::Log2 { offset } => (x + offset).log2(),
+            Shaper::Pq => rec2100_pq::from_linear(x),
+            Shaper::DavinciIntermediate => davinci_intermediate::from_linear(x),
+        }
+    }
+
+    fn decode(&self, n: f32) -> f32 {
+        use crate::transfer_functions::{blackmagic::davinci_intermediate, rec2100_pq};
+        let x = match self.shaper {
+            Shaper::Log2 { offset } => n.exp2() - offset,
+            Shaper::Pq => rec2100_pq::to_linear(n),
+            Shaper::DavinciIntermediate => davinci_intermediate::to_linear(n),
+        };
+        x / self.scale
+    }
+}
+
+/// A 3D LUT preceded by a shaper 1D LUT, as made by `bake_shaped()`.
+#[derive(Debug, Clone)]
+pub struct ShapedLut3D {
+    /// The shaper curve that was used.
+    pub shaper: Shaper,
+    /// The shaper as a 1D LUT, mapping the linear input range to
+    /// `[0.0, 1.0]`.  It has a single range and three identical tables,
+    /// as needed by e.g. the Resolve .cube format.
+    pub lut_1d: Lut1D,
+    /// The 3D LUT, with an input range of `[0.0, 1.0]`.
+    pub lut_3d: Lut3D,
+    /// The largest absolute error of `lut_1d` followed by `lut_3d`
+    /// compared to the original transform, over a set of test points
+    /// within the input range.
+    pub max_error: f32,
+}
+
+/// Bakes a transform into a shaper 1D LUT and a 3D LUT, automatically
+/// choosing the shaper.
+///
+/// This is intended for transforms on HDR or otherwise wide-range linear
+/// data, where a 3D LUT over linear input would waste most of its
+/// lattice.  `range` is the expected linear input range on all channels,
+/// and its top must be greater than zero.
+///
+/// Each candidate shaper (several `Log2` offsets and the transfer
+/// function based shapers) is baked, and the result with the lowest
+/// `max_error` is returned.  The 3D LUT is evaluated with
+/// `interpolation` when measuring the error.
+///
+/// Returns an error if `range` is invalid or its top isn't greater than
+/// zero, or if `shaper_samples` or `resolution` is less than 2.
+pub fn bake_shaped<F: Fn([f32; 3]) -> [f32; 3]>(
+    transform: F,
+    range: (f32, f32),
+    shaper_samples: usize,
+    resolution: usize,
+    interpolation: Interpolation3D,
+) -> Result<ShapedLut3D, LutError> {
+    validate_shaped(range, shaper_samples, resolution)?;
+
+    let test_points = ShapedTestPoints::new(&transform, range);
+    Ok(Shaper::candidates(range)
+        .into_iter()
+        .map(|shaper| {
+            bake_shaped_impl(
+                &transform,
+                range,
+                shaper,
+                shaper_samples,
+                resolution,
+                interpolation,
+                &test_points,
+            )
+        })
+        .min_by(|a, b| a.max_error.total_cmp(&b.max_error))
+        .unwrap())
+}
+
+/// Bakes a transform into a shaper 1D LUT and a 3D LUT, using the given
+/// shaper.
+///
+/// Otherwise the same as `bake_shaped()`, except that it's also an
+/// error if the shaper isn't defined over the whole range (i.e. a
+/// `Log2` offset that's too small).
+pub fn bake_shaped_with<F: Fn([f32; 3]) -> [f32; 3]>(
+    transform: F,
+    range: (f32, f32),
+    shaper: Shaper,
+    shaper_samples: usize,
+    resolution: usize,
+    interpolation: Interpolation3D,
+) -> Result<ShapedLut3D, LutError> {
+    validate_shaped(range, shaper_samples, resolution)?;
+    if let Shaper::Log2 { offset } = shaper {
+        let low = range.0 + offset;
+        if low.is_nan() || low <= 0.0 {
+            return Err(LutError::BadRange { index: 0 });
+        }
+    }
+
+    let test_points = ShapedTestPoints::new(&transform, range);
+    Ok(bake_shaped_impl(
+        &transform,
+        range,
+        shaper,
+        shaper_samples,
+        resolution,
+        interpolation,
+        &test_points,
+    ))
+}
+
+/// Checks the arguments shared by `bake_shaped()` and
+/// `bake_shaped_with()`.
+fn validate_shaped(
+    range: (f32, f32),
+    shaper_samples: usize,
+    resolution: usize,
+) -> Result<(), LutError> {
+    validate_range(range, 0)?;
+    if range.1 <= 0.0 {
+        return Err(LutError::BadRange { index: 0 });
+    }
+    if shaper_samples < 2 {
+        return Err(LutError::TableTooShort {
+            index: 0,
+            len: shaper_samples,
+        });
+    }
+    validate_lattice(&[(0.0, 1.0); 3], [resolution; 3]).map(|_| ())
+}
+
+fn bake_shaped_impl<F: Fn([f32; 3]) -> [f32; 3]>(
+    transform: &F,
+    range: (f32, f32),
+    shaper: Shaper,
+    shaper_samples: usize,
+    resolution: usize,
+    interpolation: Interpolation3D,
+    test_points: &ShapedTestPoints,
+) -> ShapedLut3D {
+    let curve = shaper.normalized(range);
+
+    let shaper_lut = Lut1D::from_fn_1(shaper_samples, range.0, range.1, |x| curve.shape(x));
+    let lut_1d = Lut1D {
+        ranges: shaper_lut.ranges,
+        tables: vec![shaper_lut.tables[0].clone(); 3],
+    };
+    let lut_3d = bake_3d([resolution; 3], [(0.0, 1.0); 3], |rgb| {
+        transform([
+            curve.unshape(rgb[0]),
+            curve.unshape(rgb[1]),
+            curve.unshape(rgb[2]),
+        ])
+    })
+    .expect("lattice checked by validate_shaped()");
+
+    let max_error = {
+        let sampler_1d = Sampler1D::new(&lut_1d);
+        let sampler_3d = Sampler3D::new(&lut_3d);
+        test_points.max_error(|rgb| sampler_3d.look_up(sampler_1d.look_up(rgb), interpolation))
+    };
+
+    ShapedLut3D {
+        shaper,
+        lut_1d,
+        lut_3d,
+        max_error,
+    }
+}
+
+/// Input/output pairs of a transform, for measuring the error of baked
+/// LUTs.
+///
+/// The same points are used for every shaper, so that their errors are
+/// comparable.  Along each axis they're a mix of linearly and
+/// logarithmically spaced values, so that both the top and the bottom
+/// of the range are well covered.
+struct ShapedTestPoints {
+    inputs: Vec<[f32; 3]>,
+    outputs: Vec<[f32; 3]>,
+}
+
+impl ShapedTestPoints {
+    const AXIS_POINTS: usize = 16;
+
+    fn new<F: Fn([f32; 3]) -> [f32; 3]>(transform: &F, range: (f32, f32)) -> ShapedTestPoints {
+        let n = Self::AXIS_POINTS;
+        let span = range.1 as f64 - range.0 as f64;
+        let mut axis = Vec::with_capacity(n * 2);
+        for i in 0..n {
+            // Offset by half a step, to land between lattice points.
+            let t = (i as f64 + 0.5) / n as f64;
+            axis.push((range.0 as f64 + (span * t)) as f32);
+            axis.push((range.0 as f64 + (span * (t * -16.0).exp2())) as f32);
+        }
+
+        let mut inputs = Vec::with_capacity(axis.len().pow(3));
+        for b in axis.iter() {
+            for g in axis.iter() {
+                for r in axis.iter() {
+                    inputs.push([*r, *g, *b]);
+                }
+            }
+        }
+        let outputs = inputs.iter().map(|rgb| transform(*rgb)).collect();
+
+        ShapedTestPoints { inputs, outputs }
+    }
+
+    fn max_error<F: Fn([f32; 3]) -> [f32; 3]>(&self, f: F) -> f32 {
+        let mut max_error = 0.0f32;
+        for (rgb, expected) in self.inputs.iter().zip(self.outputs.iter()) {
+            let out = f(*rgb);
+            for c in 0..3 {
+                // `max()` ignores NaN, so check it explicitly.
+                let error = (out[c] - expected[c]).abs();
+                if error.is_nan() {
+                    return f32::INFINITY;
+                }
+                max_error = max_error.max(error);
+            }
+        }
+        max_error
+    }
+}
+
+/// Error statistics from comparing two LUTs, or a LUT and a function.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ErrorStats {
+    pub max: f64,
+    pub mean: f64,
+    /// Root mean square.
+    pub rms: f64,
+    /// The input value of the sample with the largest error.
+    pub worst_input: [f32; 3],
+}
+
+/// The results of comparing two LUTs, or a LUT and a function.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Comparison {
+    /// Errors in the raw output values.  The error of each sample is
+    /// the largest absolute difference of its three channels.
+    pub raw: ErrorStats,
+    /// Errors in OkLab.  The error of each sample is the Euclidean
+    /// distance between the outputs after converting them to OkLab,
+    /// which is roughly a perceptual "delta E".
+    pub oklab: ErrorStats,
+}
+
+/// Compares two 1D LUTs.
+///
+/// The LUTs are sampled at `samples` evenly spaced points across the
+/// input ranges of `a`, with the same value on all three channels.  Each
+/// LUT must have either one table or three.
+///
+/// `to_xyz` converts the LUT outputs to CIE XYZ (with a D65 white
+/// point) for the OkLab comparison, and assumes that the outputs are
+/// linear.  E.g. `matrix::rgb_to_xyz_matrix(chroma::REC709)` for linear
+/// Rec.709/sRGB outputs.
+pub fn compare_1d(a: &Lut1D, b: &Lut1D, samples: usize, to_xyz: Matrix) -> Comparison {
+    let sampler = Sampler1D::new(b);
+    compare_1d_fn(a, |rgb| sampler.look_up(rgb), samples, to_xyz)
+}
+
+/// Compares a 1D LUT to a reference function.
+///
+/// Otherwise the same as `compare_1d()`.
+pub fn compare_1d_fn<F: Fn([f32; 3]) -> [f32; 3]>(
+    lut: &Lut1D,
+    f: F,
+    samples: usize,
+    to_xyz: Matrix,
+) -> Comparison {
+    assert!(samples >= 2);
+    let sampler = Sampler1D::new(lut);
+    let range = |chan: usize| lut.ranges[chan.min(lut.ranges.len() - 1)];
+
+    let mut errors = ErrorAccumulator::new(to_xyz);
+    for i in 0..samples {
+        let t = i as f64 / (samples - 1) as f64;
+        let mut rgb = [0.0f32; 3];
+        for (chan, n) in rgb.iter_mut().enumerate() {
+            let (min, max) = range(chan);
+            *n = min + ((max as f64 - min as f64) * t) as f32;
+        }
+        errors.add(rgb, sampler.look_up(rgb), f(rgb));
+    }
+    errors.finish()
+}
+
+/// Compares two 3D LUTs.
+///
+/// The LUTs are sampled on a grid of `samples` evenly spaced points per
+/// axis across the input range of `a`, and evaluated with
+/// `interpolation`.  Using a sample count that doesn't line up with the
+/// LUTs' lattices (e.g. 64 for a 33-point LUT) tests the interpolated
+/// values as well as the lattice points.
+///
+/// `to_xyz` is the same as in `compare_1d()`.
+pub fn compare_3d(
+    a: &Lut3D,
+    b: &Lut3D,
+    samples: usize,
+    interpolation: Interpolation3D,
+    to_xyz: Matrix,
+) -> Comparison {
+    let sampler = Sampler3D::new(b);
+    compare_3d_fn(
+        a,
+        |rgb| sampler.look_up(rgb, interpolation),
+        samples,
+        interpolation,
+        to_xyz,
+    )
+}
+
+/// Compares a 3D LUT to a reference function.
+///
+/// Otherwise the same as `compare_3d()`.
+pub fn compare_3d_fn<F: Fn([f32; 3]) -> [f32; 3]>(
+    lut: &Lut3D,
+    f: F,
+    samples: usize,
+    interpolation: Interpolation3D,
+    to_xyz: Matrix,
+) -> Comparison {
+    assert!(samples >= 2);
+    let sampler = Sampler3D::new(lut);
+    let axis = |chan: usize, i: usize| {
+        let (min, max) = lut.range[chan];
+        let t = i as f64 / (samples - 1) as f64;
+        min + ((max as f64 - min as f64) * t) as f32
+    };
+
+    let mut errors = ErrorAccumulator::new(to_xyz);
+    for bi in 0..samples {
+        for gi in 0..samples {
+            for ri in 0..samples {
+                let rgb = [axis(0, ri), axis(1, gi), axis(2, bi)];
+                errors.add(rgb, sampler.look_up(rgb, interpolation), f(rgb));
             }
         }
+    }
+    errors.finish()
+}
 
-        Lut3D {
-            range: [(min[0], max[0]), (min[1], max[1]), (min[2], max[2])],
-            resolution: resolution,
-            tables: tables,
+/// Accumulates the errors for `Comparison`.
+struct ErrorAccumulator {
+    to_xyz: Matrix,
+    raw: ErrorSums,
+    oklab: ErrorSums,
+}
+
+#[derive(Default)]
+struct ErrorSums {
+    max: f64,
+    sum: f64,
+    sum_sq: f64,
+    count: usize,
+    worst_input: [f32; 3],
+}
+
+impl ErrorSums {
+    fn add(&mut self, input: [f32; 3], error: f64) {
+        // NaN outputs count as infinitely wrong.
+        let error = if error.is_nan() { f64::INFINITY } else { error };
+        if self.count == 0 || error > self.max {
+            self.max = error;
+            self.worst_input = input;
+        }
+        self.sum += error;
+        self.sum_sq += error * error;
+        self.count += 1;
+    }
+
+    fn finish(&self) -> ErrorStats {
+        let count = self.count.max(1) as f64;
+        ErrorStats {
+            max: self.max,
+            mean: self.sum / count,
+            rms: (self.sum_sq / count).sqrt(),
+            worst_input: self.worst_input,
         }
     }
 }
 
-impl Default for Lut3D {
-    fn default() -> Lut3D {
-        Lut3D {
-            range: [(0.0, 1.0); 3],
-            resolution: [0; 3],
-            tables: Vec::new(),
+impl ErrorAccumulator {
+    fn new(to_xyz: Matrix) -> ErrorAccumulator {
+        ErrorAccumulator {
+            to_xyz,
+            raw: ErrorSums::default(),
+            oklab: ErrorSums::default(),
+        }
+    }
+
+    fn add(&mut self, input: [f32; 3], a: [f32; 3], b: [f32; 3]) {
+        let mut raw = 0.0f64;
+        for c in 0..3 {
+            let error = (a[c] as f64 - b[c] as f64).abs();
+            if error.is_nan() || error > raw {
+                raw = error;
+            }
+        }
+        self.raw.add(input, raw);
+
+        let to_oklab = |rgb: [f32; 3]| {
+            let rgb = [rgb[0] as f64, rgb[1] as f64, rgb[2] as f64];
+            oklab::from_xyz_d65(transform_color(rgb, self.to_xyz))
+        };
+        let (lab_a, lab_b) = (to_oklab(a), to_oklab(b));
+        let distance = ((lab_a[0] - lab_b[0]).powi(2)
+            + (lab_a[1] - lab_b[1]).powi(2)
+            + (lab_a[2] - lab_b[2]).powi(2))
+        .sqrt();
+        self.oklab.add(input, distance);
+    }
+
+    fn finish(&self) -> Comparison {
+        Comparison {
+            raw: self.raw.finish(),
+            oklab: self.oklab.finish(),
         }
     }
 }
@@ -361,6 +2940,30 @@ pub fn resample(
     new_table
 }
 
+/// Like `resample()`, but with a choice of interpolation method.
+pub fn resample_with(
+    new_samples: usize,
+    new_range_x: (f32, f32),
+    old_table: &[f32],
+    old_range_x: (f32, f32),
+    interpolation: Interpolation1D,
+) -> Vec<f32> {
+    assert!(new_samples >= 2 && old_table.len() >= 2);
+    if interpolation == Interpolation1D::Linear {
+        return resample(new_samples, new_range_x, old_table, old_range_x);
+    }
+
+    let new_norm = (new_range_x.1 as f64 - new_range_x.0 as f64) / (new_samples - 1) as f64;
+    let old_norm = (old_table.len() - 1) as f64 / (old_range_x.1 as f64 - old_range_x.0 as f64);
+    (0..new_samples)
+        .map(|i| {
+            let x = new_range_x.0 as f64 + (i as f64 * new_norm);
+            let j = (x - old_range_x.0 as f64) * old_norm;
+            sample_table(old_table, j as f32, interpolation)
+        })
+        .collect()
+}
+
 //-------------------------------------------------------------
 
 /// Helper function for inverting 1D LUTs.
@@ -597,4 +3200,973 @@ mod tests {
             assert_feq(lut_inv[i], y, 0.00001);
         }
     }
+
+    #[test]
+    fn lut_3d_look_up_01() {
+        // Affine functions are reproduced exactly by both interpolation
+        // methods, including with non-cubic resolutions and ranges.
+        let f = |(r, g, b): (f32, f32, f32)| (r, (g * 0.5) + b, 1.0 - (r * 2.0));
+        let lut = Lut3D::from_fn([3, 4, 5], [-1.0, 0.0, 0.5], [2.0, 1.0, 4.5], f);
+
+        for rgb in [
+            [0.3, 0.7, 1.1],
+            [-1.0, 0.0, 0.5],
+            [2.0, 1.0, 4.5],
+            [1.9, 0.1, 3.3],
+            [0.5, 0.5, 2.5],
+        ] {
+            let (r, g, b) = f((rgb[0], rgb[1], rgb[2]));
+            for out in [lut.look_up_trilinear(rgb), lut.look_up_tetrahedral(rgb)] {
+                assert_feq(out[0], r, 0.00001);
+                assert_feq(out[1], g, 0.00001);
+                assert_feq(out[2], b, 0.00001);
+            }
+        }
+    }
+
+    #[test]
+    fn lut_3d_look_up_02() {
+        let lut = Lut3D::from_fn([2, 2, 2], [0.0; 3], [1.0; 3], |(r, g, b)| {
+            (r * g * b, r * g * b, r * g * b)
+        });
+
+        // Out-of-range inputs are clamped.
+        assert_eq!(lut.look_up_trilinear([2.0, 1.5, 1.0]), [1.0; 3]);
+        assert_eq!(lut.look_up_tetrahedral([-1.0, 0.5, 0.5]), [0.0; 3]);
+
+        // Trilinear interpolation reproduces multilinear functions.
+        assert_feq(lut.look_up_trilinear([0.5, 0.5, 0.5])[0], 0.125, 0.00001);
+
+        // Tetrahedral interpolation only uses the neutral lattice
+        // points along the neutral axis.
+        assert_feq(lut.look_up_tetrahedral([0.5, 0.5, 0.5])[0], 0.5, 0.00001);
+    }
+
+    #[test]
+    fn lut_1d_apply_01() {
+        let lut = Lut1D::from_fn_3(
+            5,
+            [0.0, -1.0, 0.0],
+            [1.0, 1.0, 4.0],
+            (|n: f32| n * n, |n: f32| n * 0.5, |n: f32| n.sqrt()),
+        );
+
+        let mut pixels = [[0.5, 0.5, 2.0], [2.0, -3.0, 1.5]];
+        let expected: Vec<[f32; 3]> = pixels
+            .iter()
+            .map(|rgb| {
+                [
+                    lut.look_up(rgb[0], 0),
+                    lut.look_up(rgb[1], 1),
+                    lut.look_up(rgb[2], 2),
+                ]
+            })
+            .collect();
+        lut.apply(&mut pixels);
+        assert_eq!(&pixels[..], &expected[..]);
+
+        // A single table applies to all channels, and alpha is untouched.
+        let lut = Lut1D::from_fn_1(3, 0.0, 2.0, |n| n * 2.0);
+        let mut data = [0.5, 1.0, 1.5, 0.25, 2.0, 3.0, -1.0, 0.75];
+        lut.apply_interleaved(&mut data, 4);
+        assert_eq!(data, [1.0, 2.0, 3.0, 0.25, 4.0, 4.0, 0.0, 0.75]);
+    }
+
+    #[test]
+    fn lut_3d_apply_01() {
+        let lut = Lut3D::from_fn([5, 3, 4], [0.0; 3], [1.0, 2.0, 1.0], |(r, g, b)| {
+            (r * g, g.sqrt(), b * b + r)
+        });
+        let pixels = [[0.1, 0.2, 0.3], [0.9, 1.7, 0.5], [-1.0, 3.0, 0.45]];
+
+        for interpolation in [Interpolation3D::Trilinear, Interpolation3D::Tetrahedral] {
+            let look_up = |rgb| match interpolation {
+                Interpolation3D::Trilinear => lut.look_up_trilinear(rgb),
+                Interpolation3D::Tetrahedral => lut.look_up_tetrahedral(rgb),
+            };
+
+            let mut out = pixels;
+            lut.apply(&mut out, interpolation);
+            for (a, b) in out.iter().zip(pixels.iter()) {
+                assert_eq!(*a, look_up(*b));
+            }
+
+            let mut data: Vec<f32> = pixels
+                .iter()
+                .flat_map(|p| [p[0], p[1], p[2], 0.5])
+                .collect();
+            lut.apply_interleaved(&mut data, 4, interpolation);
+            for (a, b) in data.chunks_exact(4).zip(pixels.iter()) {
+                assert_eq!(a[..3], look_up(*b));
+                assert_eq!(a[3], 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn lut_3d_resample_01() {
+        let f = |(r, g, b): (f32, f32, f32)| ((r * 0.5) + g, g - b, 1.0 - r);
+        let lut = Lut3D::from_fn([33, 33, 33], [0.0; 3], [1.0; 3], f);
+
+        // Down to a smaller, non-cubic resolution.
+        let lut2 = lut
+            .resample([17, 9, 5], lut.range, Interpolation3D::Tetrahedral)
+            .unwrap();
+        let expected = Lut3D::from_fn([17, 9, 5], [0.0; 3], [1.0; 3], f);
+        assert_eq!(lut2.resolution, [17, 9, 5]);
+        assert_eq!(lut2.range, lut.range);
+        for (a, b) in lut2
+            .tables
+            .iter()
+            .flatten()
+            .zip(expected.tables.iter().flatten())
+        {
+            assert_feq(*a, *b, 0.00001);
+        }
+
+        // To a wider domain, which clamps at the original boundary.
+        let lut3 = lut
+            .resample([3, 3, 3], [(-1.0, 1.0); 3], Interpolation3D::Trilinear)
+            .unwrap();
+        assert_eq!(lut3.range, [(-1.0, 1.0); 3]);
+        assert_eq!(lut3.look_up_trilinear([-1.0, -1.0, -1.0]), [0.0, 0.0, 1.0]);
+        assert_eq!(lut3.look_up_trilinear([1.0, 1.0, 1.0]), [1.5, 0.0, 0.0]);
+        assert_feq(lut3.look_up_trilinear([0.0, 1.0, 0.0])[0], 1.0, 0.00001);
+    }
+
+    #[test]
+    fn lut_3d_invert_01() {
+        // An invertible function, whose output range is smaller than the
+        // bounding box used for the inverse.
+        let f = |(r, g, b): (f32, f32, f32)| {
+            (
+                r * r,
+                (g * 0.75) + (r * 0.25),
+                (b * 0.5) + (b * b * 0.5) - (g * 0.1),
+            )
+        };
+        let lut = Lut3D::from_fn([17, 17, 17], [0.0; 3], [1.0; 3], f);
+
+        let tolerance = 0.0001;
+        let inverse = lut.invert([9, 9, 9], [(0.0, 1.0); 3], tolerance).unwrap();
+        assert_eq!(inverse.lut.resolution, [9, 9, 9]);
+        assert!(!inverse.unreachable.is_empty());
+
+        let lattice = Lut3D::from_fn([9, 9, 9], [0.0; 3], [1.0; 3], |rgb| rgb);
+        for i in 0..lattice.tables[0].len() {
+            let target = [
+                lattice.tables[0][i],
+                lattice.tables[1][i],
+                lattice.tables[2][i],
+            ];
+            let x = [
+                inverse.lut.tables[0][i],
+                inverse.lut.tables[1][i],
+                inverse.lut.tables[2][i],
+            ];
+            let out = lut.look_up_trilinear(x);
+            let unreachable = inverse.unreachable.iter().find(|p| p.target == target);
+
+            match unreachable {
+                None => {
+                    for c in 0..3 {
+                        assert_feq(out[c], target[c], tolerance);
+                    }
+                }
+                Some(p) => {
+                    assert_eq!(p.closest, out);
+                    assert!((0..3).any(|c| (out[c] - target[c]).abs() > tolerance));
+                }
+            }
+        }
+
+        // Points that are definitely in gamut are reachable, and points
+        // that definitely aren't are not.
+        let in_gamut = [4, 4, 4];
+        let out_of_gamut = [8, 0, 4];
+        assert!(!inverse.unreachable.iter().any(|p| p.index == in_gamut));
+        assert!(inverse.unreachable.iter().any(|p| p.index == out_of_gamut));
+    }
+
+    #[test]
+    fn lut_3d_invert_02() {
+        // The inverse of an identity LUT is an identity LUT.
+        let lut = Lut3D::from_fn([5, 4, 3], [-1.0; 3], [2.0; 3], |rgb| rgb);
+        let inverse = lut.invert([4, 4, 4], lut.range, 0.00001).unwrap();
+
+        assert!(inverse.unreachable.is_empty());
+        let identity = Lut3D::from_fn([4, 4, 4], [-1.0; 3], [2.0; 3], |rgb| rgb);
+        for (a, b) in inverse
+            .lut
+            .tables
+            .iter()
+            .flatten()
+            .zip(identity.tables.iter().flatten())
+        {
+            assert_feq(*a, *b, 0.00001);
+        }
+
+        // Invalid forward LUTs and inverse lattices are errors.
+        assert_eq!(
+            lut.invert([4, 1, 4], lut.range, 0.00001).unwrap_err(),
+            LutError::ResolutionTooLow {
+                axis: 1,
+                resolution: 1
+            }
+        );
+        let reversed = [(0.0, 1.0), (1.0, 0.0), (0.0, 1.0)];
+        assert_eq!(
+            lut.invert([4; 3], reversed, 0.00001).unwrap_err(),
+            LutError::BadRange { index: 1 }
+        );
+        let mut bad = lut.clone();
+        bad.range[2] = (f32::NAN, 1.0);
+        assert_eq!(
+            bad.invert([4; 3], lut.range, 0.00001).unwrap_err(),
+            LutError::BadRange { index: 2 }
+        );
+        assert!(bad
+            .resample([4; 3], lut.range, Interpolation3D::Trilinear)
+            .is_err());
+    }
+
+    #[test]
+    fn compose_01() {
+        let shaper = Lut1D::from_fn_1(65, 0.0, 4.0, |n| n.sqrt() * 0.5);
+        let cube = Lut3D::from_fn([17; 3], [0.0; 3], [1.0; 3], |(r, g, b)| {
+            (g, b * 0.5 + 0.25, r * r)
+        });
+        let curve = Lut1D::from_fn_3(
+            33,
+            [0.0; 3],
+            [1.0; 3],
+            (|n: f32| n * 2.0, |n: f32| n - 1.0, |n: f32| n * n),
+        );
+        let apply_curve = |rgb: [f32; 3]| {
+            [
+                curve.look_up(rgb[0], 0),
+                curve.look_up(rgb[1], 1),
+                curve.look_up(rgb[2], 2),
+            ]
+        };
+
+        // Checks that each lattice point of `lut` has the value of `f`.
+        let check = |lut: &Lut3D, f: &dyn Fn([f32; 3]) -> [f32; 3]| {
+            let r = lut.range;
+            let inputs = Lut3D::from_fn(
+                lut.resolution,
+                [r[0].0, r[1].0, r[2].0],
+                [r[0].1, r[1].1, r[2].1],
+                |rgb| rgb,
+            );
+            for i in 0..lut.tables[0].len() {
+                let out = f([
+                    inputs.tables[0][i],
+                    inputs.tables[1][i],
+                    inputs.tables[2][i],
+                ]);
+                for c in 0..3 {
+                    assert_feq(lut.tables[c][i], out[c], 0.00001);
+                }
+            }
+        };
+
+        let tetra = Interpolation3D::Tetrahedral;
+        check(
+            &compose_1d_3d(&shaper, &cube, [9, 5, 3], [(0.0, 4.0); 3], tetra).unwrap(),
+            &|rgb| {
+                let shaped = rgb.map(|n| shaper.look_up(n, 0));
+                cube.look_up_tetrahedral(shaped)
+            },
+        );
+        check(
+            &compose_3d_1d(&cube, &curve, [9; 3], [(-0.5, 1.0); 3], tetra).unwrap(),
+            &|rgb| apply_curve(cube.look_up_tetrahedral(rgb)),
+        );
+        check(
+            &compose_3d_3d(&cube, &cube, [7; 3], cube.range, tetra).unwrap(),
+            &|rgb| cube.look_up_tetrahedral(cube.look_up_tetrahedral(rgb)),
+        );
+
+        let lut = compose_1d_1d(&shaper, &curve, 5).unwrap();
+        assert_eq!(lut.ranges, vec![(0.0, 4.0); 3]);
+        for i in 0..5 {
+            let n = i as f32;
+            let out = apply_curve([shaper.look_up(n, 0); 3]);
+            for c in 0..3 {
+                assert_feq(lut.tables[c][i], out[c], 0.00001);
+            }
+        }
+
+        // Invalid arguments.
+        let two_tables = Lut1D {
+            ranges: vec![(0.0, 1.0)],
+            tables: vec![vec![0.0, 1.0]; 2],
+        };
+        assert_eq!(
+            compose_1d_1d(&shaper, &two_tables, 5).unwrap_err(),
+            LutError::WrongTableCount {
+                expected: 3,
+                found: 2
+            }
+        );
+        assert_eq!(
+            compose_1d_1d(&shaper, &curve, 1).unwrap_err(),
+            LutError::TableTooShort { index: 0, len: 1 }
+        );
+        assert!(compose_3d_3d(&cube, &cube, [7, 7, 0], cube.range, tetra).is_err());
+        assert!(compose_1d_3d(&two_tables, &cube, [7; 3], cube.range, tetra).is_err());
+    }
+
+    #[test]
+    fn bake_shaped_01() {
+        // A simple HDR tone map, with a little channel crosstalk.
+        let transform = |rgb: [f32; 3]| {
+            let tm = |n: f32| n / (1.0 + n);
+            [
+                tm(rgb[0] * 0.9 + rgb[1] * 0.1),
+                tm(rgb[1]),
+                tm(rgb[2] * 0.8 + rgb[0] * 0.2),
+            ]
+        };
+        let range = (0.0, 100.0);
+        let tetra = Interpolation3D::Tetrahedral;
+
+        let baked = bake_shaped(transform, range, 1024, 33, tetra).unwrap();
+        assert_eq!(baked.lut_1d.ranges, vec![range]);
+        assert_eq!(baked.lut_1d.tables.len(), 3);
+        assert_feq(baked.lut_1d.tables[0][0], 0.0, 0.00001);
+        assert_feq(*baked.lut_1d.tables[0].last().unwrap(), 1.0, 0.00001);
+        assert_eq!(baked.lut_3d.range, [(0.0, 1.0); 3]);
+        assert_eq!(baked.lut_3d.resolution, [33; 3]);
+        assert!(baked.max_error < 0.005);
+
+        // The chosen shaper should be the best of the candidates.
+        for shaper in Shaper::candidates(range) {
+            let other = bake_shaped_with(transform, range, shaper, 1024, 33, tetra).unwrap();
+            assert!(baked.max_error <= other.max_error);
+        }
+
+        // And much better than no shaping at all.
+        let linear = bake_3d([33; 3], [range; 3], transform).unwrap();
+        let pixel = [0.05, 0.1, 0.02];
+        let expected = transform(pixel);
+        let linear_error = (linear.look_up_tetrahedral(pixel)[1] - expected[1]).abs();
+        assert!(linear_error > baked.max_error * 10.0);
+
+        // Invalid arguments.
+        assert_eq!(
+            bake_shaped(transform, (0.0, 0.0), 1024, 33, tetra).unwrap_err(),
+            LutError::BadRange { index: 0 }
+        );
+        assert_eq!(
+            bake_shaped(transform, (-2.0, -1.0), 1024, 33, tetra).unwrap_err(),
+            LutError::BadRange { index: 0 }
+        );
+        assert_eq!(
+            bake_shaped(transform, range, 1, 33, tetra).unwrap_err(),
+            LutError::TableTooShort { index: 0, len: 1 }
+        );
+        let log2 = Shaper::Log2 { offset: 0.0 };
+        assert_eq!(
+            bake_shaped_with(transform, range, log2, 1024, 33, tetra).unwrap_err(),
+            LutError::BadRange { index: 0 }
+        );
+    }
+
+    #[test]
+    fn interpolation_1d_01() {
+        let curve = |n: f32| n.powf(1.0 / 2.2);
+        let sparse = Lut1D::from_fn_1(17, 0.0, 1.0, curve);
+
+        for interpolation in [
+            Interpolation1D::Linear,
+            Interpolation1D::CatmullRom,
+            Interpolation1D::MonotoneCubic,
+        ] {
+            // Passes through the samples exactly.
+            for i in 0..17 {
+                let x = i as f32 / 16.0;
+                assert_feq(
+                    sparse.look_up_with(x, 0, interpolation),
+                    sparse.tables[0][i],
+                    0.000001,
+                );
+            }
+
+            let dense = sparse.resample(1024, interpolation).unwrap();
+            assert_eq!(dense.ranges, sparse.ranges);
+            assert_eq!(dense.tables[0].len(), 1024);
+            assert!(dense.is_monotonic());
+        }
+
+        // The smooth modes should track the curve better than linear
+        // away from the steep toe.
+        let max_error = |interpolation| {
+            let dense = sparse.resample(1024, interpolation).unwrap();
+            (256..1024)
+                .map(|i| (dense.tables[0][i] - curve(i as f32 / 1023.0)).abs())
+                .fold(0.0f32, f32::max)
+        };
+        let linear = max_error(Interpolation1D::Linear);
+        assert!(max_error(Interpolation1D::CatmullRom) < linear * 0.25);
+        assert!(max_error(Interpolation1D::MonotoneCubic) < linear * 0.5);
+    }
+
+    #[test]
+    fn interpolation_1d_02() {
+        // A sharp step, which Catmull-Rom overshoots but monotone cubic
+        // doesn't.
+        let table = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        let catmull_rom = resample_with(
+            256,
+            (0.0, 1.0),
+            &table,
+            (0.0, 1.0),
+            Interpolation1D::CatmullRom,
+        );
+        let monotone = resample_with(
+            256,
+            (0.0, 1.0),
+            &table,
+            (0.0, 1.0),
+            Interpolation1D::MonotoneCubic,
+        );
+
+        assert!(catmull_rom.iter().any(|n| *n < 0.0 || *n > 1.0));
+        assert!(monotone.iter().all(|n| *n >= 0.0 && *n <= 1.0));
+        assert!(monotone.windows(2).all(|w| w[0] <= w[1]));
+        assert_feq(monotone[0], 0.0, 0.0);
+        assert_feq(monotone[255], 1.0, 0.0);
+    }
+
+    #[test]
+    fn lut_1d_non_uniform_01() {
+        let lut = Lut1DNonUniform {
+            tables: vec![
+                vec![(0.0, 0.0), (4.0, 1.0)],
+                vec![(0.0, 0.0), (0.5, 0.75), (1.0, 1.0)],
+                vec![(-1.0, 0.0), (1.0, 1.0)],
+            ],
+        };
+
+        assert_eq!(lut.look_up(-1.0, 0), 0.0);
+        assert_eq!(lut.look_up(1.0, 0), 0.25);
+        assert_eq!(lut.look_up(0.25, 1), 0.375);
+        assert_eq!(lut.look_up(0.75, 1), 0.875);
+        assert_eq!(lut.look_up(2.0, 1), 1.0);
+
+        assert_eq!(lut.look_up_inv(0.25, 0), 1.0);
+        assert_eq!(lut.look_up_inv(0.875, 1), 0.75);
+        assert_eq!(lut.look_up_inv(0.75, 1), 0.5);
+        assert_eq!(lut.look_up_inv(-1.0, 2), -1.0);
+        assert_eq!(lut.look_up_inv(2.0, 2), 1.0);
+
+        let uniform = lut.to_lut_1d(6);
+        assert_eq!(uniform.ranges, vec![(-1.0, 4.0)]);
+        assert_eq!(&uniform.tables[0], &[0.0, 0.0, 0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(&uniform.tables[1], &[0.0, 0.0, 1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(&uniform.tables[2], &[0.0, 0.5, 1.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn lut_1d_non_uniform_02() {
+        // Round trip through the non-uniform representation is lossless.
+        let lut = Lut1D::from_fn_3(
+            5,
+            [0.0, -1.0, 0.0],
+            [1.0, 1.0, 2.0],
+            (|n: f32| n * n, |n: f32| n * 0.5, |n: f32| n.sqrt()),
+        );
+        let non_uniform = Lut1DNonUniform::from_lut_1d(&lut).unwrap();
+        assert_eq!(
+            non_uniform.tables[1],
+            vec![
+                (-1.0, -0.5),
+                (-0.5, -0.25),
+                (0.0, 0.0),
+                (0.5, 0.25),
+                (1.0, 0.5)
+            ]
+        );
+        for chan in 0..3 {
+            let range = lut.ranges[chan];
+            let round_trip = Lut1DNonUniform {
+                tables: vec![non_uniform.tables[chan].clone()],
+            }
+            .to_lut_1d(5);
+            assert_eq!(round_trip.ranges, vec![range]);
+            assert_eq!(round_trip.tables[0], lut.tables[chan]);
+        }
+
+        // Invalid LUTs.
+        let empty = Lut1D {
+            ranges: vec![(0.0, 1.0)],
+            tables: vec![vec![]],
+        };
+        assert_eq!(
+            Lut1DNonUniform::from_lut_1d(&empty),
+            Err(LutError::TableTooShort { index: 0, len: 0 })
+        );
+        let single = Lut1D {
+            ranges: vec![(0.0, 1.0)],
+            tables: vec![vec![0.5]],
+        };
+        assert_eq!(
+            Lut1DNonUniform::from_lut_1d(&single),
+            Err(LutError::TableTooShort { index: 0, len: 1 })
+        );
+        let mismatched = Lut1D {
+            ranges: vec![(0.0, 1.0), (0.0, 1.0)],
+            tables: vec![vec![0.0, 1.0]; 3],
+        };
+        assert!(Lut1DNonUniform::from_lut_1d(&mismatched).is_err());
+
+        // A flat span inverts to its middle.
+        let flat = Lut1DNonUniform {
+            tables: vec![vec![(0.0, 0.0), (1.0, 0.5), (3.0, 0.5), (4.0, 1.0)]],
+        };
+        assert_eq!(flat.look_up_inv(0.5, 0), 2.0);
+    }
+
+    #[test]
+    fn f16_bits_01() {
+        assert_eq!(f16_bits_to_f32(0x0000), 0.0);
+        assert_eq!(f16_bits_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_bits_to_f32(0xc000), -2.0);
+        assert_eq!(f16_bits_to_f32(0x0001), 2.0f32.powi(-24));
+        assert_eq!(f16_bits_to_f32(0x03ff), 2.0f32.powi(-14) - 2.0f32.powi(-24));
+        assert_eq!(f16_bits_to_f32(HALF_MAX_BITS), HALF_MAX);
+        assert_eq!(f16_bits_to_f32(HALF_INF), f32::INFINITY);
+        assert!(f16_bits_to_f32(HALF_NAN).is_nan());
+
+        // Truncation round trips for every finite positive f16.
+        for bits in 0..=HALF_MAX_BITS {
+            let n = f16_bits_to_f32(bits);
+            assert_eq!(f32_to_f16_bits_trunc(n), bits);
+            if bits < HALF_MAX_BITS {
+                let next = f16_bits_to_f32(bits + 1);
+                let mid = n + ((next - n) * 0.5);
+                assert_eq!(f32_to_f16_bits_trunc(mid), bits);
+            }
+        }
+    }
+
+    #[test]
+    fn lut_1d_half_domain_01() {
+        use crate::transfer_functions::rec2100_pq;
+
+        let lut = Lut1DHalfDomain::from_fn_1(rec2100_pq::from_linear);
+        assert_eq!(lut.tables[0].len(), Lut1DHalfDomain::SAMPLES);
+        assert!(lut.tables[0].iter().all(|n| n.is_finite()));
+
+        // Exact at f16 values, and close elsewhere.
+        for n in [0.0, 1.0, 100.0, 1000.0, 10000.0, -100.0] {
+            assert_eq!(lut.look_up(n, 0), rec2100_pq::from_linear(n));
+        }
+        for n in [0.3, 47.1, 1234.5, 5001.0, -0.02] {
+            assert_feq(lut.look_up(n, 0), rec2100_pq::from_linear(n), 0.0001);
+        }
+
+        // Inversion.
+        for n in [0.0, 0.01, 0.3, 47.1, 1234.5, 5001.0, -0.02] {
+            let y = lut.look_up(n, 0);
+            assert_feq(lut.look_up_inv(y, 0), n, n.abs() * 0.001 + 0.00001);
+        }
+        assert_eq!(lut.look_up_inv(2.0, 0), HALF_MAX);
+        assert_eq!(lut.look_up_inv(-2.0, 0), -HALF_MAX);
+        assert!(lut.look_up_inv(f32::NAN, 0).is_nan());
+
+        // Non-finite inputs.
+        assert_eq!(lut.look_up(f32::NAN, 0), 0.0);
+        assert_eq!(
+            lut.look_up(f32::INFINITY, 0),
+            rec2100_pq::from_linear(HALF_MAX)
+        );
+        assert_eq!(lut.look_up(1.0e10, 0), rec2100_pq::from_linear(HALF_MAX));
+    }
+
+    #[test]
+    fn lut_1d_half_domain_02() {
+        // Non-finite outputs are sanitized.
+        let lut = Lut1DHalfDomain::from_fn_3((|n: f32| n.log2(), |n: f32| 1.0 / n, |n: f32| n * n));
+        assert!(lut.tables.iter().flatten().all(|n| n.is_finite()));
+        assert_eq!(lut.look_up(-1.0, 0), 0.0);
+        assert_eq!(lut.look_up(0.0, 0), f32::MIN);
+        assert_eq!(lut.look_up(0.0, 1), f32::MAX);
+        assert_eq!(lut.look_up(f32::INFINITY, 1), 0.0);
+        assert_eq!(lut.look_up(f32::NEG_INFINITY, 2), HALF_MAX * HALF_MAX);
+    }
+
+    #[test]
+    fn look_up_inv_edge_cases() {
+        let lut = Lut1D::from_fn_1(5, 0.0, 1.0, |n| n * 2.0);
+
+        // Exact matches at the ends, and beyond them.
+        assert_eq!(lut.look_up_inv(0.0, 0), 0.0);
+        assert_eq!(lut.look_up_inv(2.0, 0), 1.0);
+        assert_eq!(lut.look_up_inv(3.0, 0), 1.5);
+        assert!(lut.look_up_inv(f32::NAN, 0).is_nan());
+
+        // NaN in the table.
+        let lut = Lut1D {
+            ranges: vec![(0.0, 1.0)],
+            tables: vec![vec![0.0, f32::NAN, 1.0]],
+        };
+        assert!(lut.look_up_inv(0.5, 0).is_nan());
+    }
+
+    #[test]
+    fn try_look_up_inv_01() {
+        use InversionStrategy::*;
+
+        // Rises to 1.0, dips to 0.5, then rises to 2.0, with inputs at
+        // 0, 1, 2, and 3.
+        let lut = Lut1D {
+            ranges: vec![(0.0, 3.0)],
+            tables: vec![vec![0.0, 1.0, 0.5, 2.0]],
+        };
+
+        assert_eq!(
+            lut.try_look_up_inv(0.75, 0, Error),
+            Err(InversionError::NonMonotonic {
+                channel: 0,
+                index: 2
+            })
+        );
+        assert_eq!(lut.try_look_up_inv(0.75, 0, FirstCrossing), Ok(0.75));
+        assert_eq!(
+            lut.try_look_up_inv(0.75, 0, LastCrossing),
+            Ok(2.0 + (1.0 / 6.0))
+        );
+        assert_eq!(lut.try_look_up_inv(1.0, 0, FirstCrossing), Ok(1.0));
+        assert_eq!(
+            lut.try_look_up_inv(1.0, 0, LastCrossing),
+            Ok(2.0 + (1.0 / 3.0))
+        );
+        // Monotonized to [0.0, 1.0, 1.0, 2.0].
+        assert_eq!(lut.try_look_up_inv(0.75, 0, Monotonize), Ok(0.75));
+        assert_eq!(lut.try_look_up_inv(1.0, 0, Monotonize), Ok(1.5));
+        assert_eq!(lut.try_look_up_inv(1.5, 0, Monotonize), Ok(2.5));
+
+        // Out of range.
+        assert_eq!(lut.try_look_up_inv(-1.0, 0, Monotonize), Ok(0.0));
+        assert_eq!(lut.try_look_up_inv(5.0, 0, LastCrossing), Ok(3.0));
+
+        // Flat spans in monotonic tables.
+        let flat = Lut1D {
+            ranges: vec![(0.0, 4.0)],
+            tables: vec![vec![0.0, 0.5, 0.5, 0.5, 1.0]],
+        };
+        assert_eq!(flat.try_look_up_inv(0.5, 0, Error), Ok(2.0));
+        assert_eq!(flat.try_look_up_inv(0.5, 0, FirstCrossing), Ok(1.0));
+        assert_eq!(flat.try_look_up_inv(0.5, 0, LastCrossing), Ok(3.0));
+
+        // Un-invertable tables.
+        let nan = Lut1D {
+            ranges: vec![(0.0, 1.0)],
+            tables: vec![vec![0.0, f32::NAN, 1.0]],
+        };
+        assert_eq!(
+            nan.try_look_up_inv(0.5, 0, Monotonize),
+            Err(InversionError::NonFiniteValue {
+                channel: 0,
+                index: 1
+            })
+        );
+    }
+
+    #[test]
+    fn try_resample_inverted_01() {
+        let lut = Lut1D::from_fn_3(
+            33,
+            [0.0; 3],
+            [1.0; 3],
+            (
+                |n: f32| n * n,
+                |n: f32| n * 2.0 - 1.0,
+                |n: f32| n + (n * 12.0).sin() * 0.1,
+            ),
+        );
+
+        assert!(lut
+            .try_resample_inverted(64, InversionStrategy::Error)
+            .is_err());
+
+        let inv = lut
+            .try_resample_inverted(64, InversionStrategy::Monotonize)
+            .unwrap();
+        assert_eq!(inv.ranges.len(), 3);
+        assert_eq!(inv.ranges[1], (-1.0, 1.0));
+        assert!(inv.is_monotonic());
+        for n in [0.3, 0.5, 0.9] {
+            assert_feq(inv.look_up(lut.look_up(n, 0), 0), n, 0.01);
+            assert_feq(inv.look_up(lut.look_up(n, 1), 1), n, 0.01);
+        }
+    }
+
+    #[test]
+    fn compare_01() {
+        let to_xyz = crate::matrix::rgb_to_xyz_matrix(crate::chroma::REC709);
+        let f = |(r, g, b): (f32, f32, f32)| (r * r, g, (b + r) * 0.5);
+        let lut = Lut3D::from_fn([9; 3], [0.0; 3], [1.0; 3], f);
+        let tetra = Interpolation3D::Tetrahedral;
+
+        // Identical LUTs.
+        let same = compare_3d(&lut, &lut.clone(), 16, tetra, to_xyz);
+        assert_eq!(same.raw.max, 0.0);
+        assert_eq!(same.oklab.rms, 0.0);
+
+        // A LUT vs. its reference function, where only the red channel
+        // is nonlinear and has interpolation error.
+        let cmp = compare_3d_fn(
+            &lut,
+            |[r, g, b]| {
+                let (r, g, b) = f((r, g, b));
+                [r, g, b]
+            },
+            16,
+            tetra,
+            to_xyz,
+        );
+        let max_error = (1.0f64 / 8.0).powi(2) / 4.0;
+        assert!(cmp.raw.max > 0.0 && cmp.raw.max <= max_error + 0.000001);
+        assert!(cmp.raw.mean <= cmp.raw.rms && cmp.raw.rms <= cmp.raw.max);
+        assert!(cmp.oklab.max > 0.0);
+        assert!(cmp.oklab.mean <= cmp.oklab.rms && cmp.oklab.rms <= cmp.oklab.max);
+
+        // A single changed lattice point is found as the worst sample.
+        let mut lut2 = lut.clone();
+        let i = 4 + (2 * 9) + (7 * 81);
+        lut2.tables[1][i] += 0.5;
+        let cmp = compare_3d(&lut, &lut2, 9, tetra, to_xyz);
+        assert_eq!(cmp.raw.max, 0.5);
+        assert_eq!(cmp.raw.worst_input, [0.5, 0.25, 0.875]);
+        assert_eq!(cmp.oklab.worst_input, [0.5, 0.25, 0.875]);
+    }
+
+    #[test]
+    fn compare_02() {
+        let to_xyz = crate::matrix::rgb_to_xyz_matrix(crate::chroma::REC709);
+        let a = Lut1D::from_fn_1(17, 0.0, 2.0, |n| n * 0.5);
+        let b = Lut1D::from_fn_1(5, 0.0, 2.0, |n| n * 0.5);
+
+        let cmp = compare_1d(&a, &b, 33, to_xyz);
+        assert!(cmp.raw.max < 0.000001);
+        assert!(cmp.oklab.max < 0.000001);
+
+        // NaNs are the worst possible error.
+        let cmp = compare_1d_fn(
+            &a,
+            |rgb| if rgb[0] == 1.0 { [f32::NAN; 3] } else { rgb },
+            33,
+            to_xyz,
+        );
+        assert_eq!(cmp.raw.max, f64::INFINITY);
+        assert_eq!(cmp.raw.worst_input, [1.0; 3]);
+    }
+
+    #[test]
+    fn validate_01() {
+        assert!(Lut1D::new(vec![(0.0, 1.0)], vec![vec![0.0, 1.0]; 3]).is_ok());
+        assert_eq!(
+            Lut1D::new(vec![(0.0, 1.0); 2], vec![vec![0.0, 1.0]; 3]).unwrap_err(),
+            LutError::RangeCountMismatch {
+                ranges: 2,
+                tables: 3
+            }
+        );
+        assert_eq!(
+            Lut1D::new(vec![(0.0, f32::NAN)], vec![vec![0.0, 1.0]]).unwrap_err(),
+            LutError::BadRange { index: 0 }
+        );
+        assert_eq!(
+            Lut1D::new(vec![(0.0, 1.0)], vec![vec![0.0, 1.0], vec![0.5]]).unwrap_err(),
+            LutError::TableTooShort { index: 1, len: 1 }
+        );
+        assert_eq!(Lut1D::default().validate(), Err(LutError::NoTables));
+
+        let lut = Lut3D::from_fn([2, 3, 4], [0.0; 3], [1.0; 3], |rgb| rgb);
+        assert_eq!(lut.validate(), Ok(()));
+        assert!(Lut3D::new(lut.range, lut.resolution, lut.tables.clone()).is_ok());
+        assert_eq!(
+            Lut3D::new(lut.range, [2, 3, 5], lut.tables.clone()).unwrap_err(),
+            LutError::TableLengthMismatch {
+                index: 0,
+                expected: 30,
+                found: 24
+            }
+        );
+        assert_eq!(
+            Lut3D::new(lut.range, [1, 3, 8], lut.tables.clone()).unwrap_err(),
+            LutError::ResolutionTooLow {
+                axis: 0,
+                resolution: 1
+            }
+        );
+        assert_eq!(
+            Lut3D::new(
+                lut.range,
+                [usize::MAX / 2, usize::MAX / 2, 2],
+                lut.tables.clone()
+            )
+            .unwrap_err(),
+            LutError::ResolutionTooHigh
+        );
+        assert_eq!(
+            Lut3D::new(lut.range, lut.resolution, lut.tables[..2].to_vec()).unwrap_err(),
+            LutError::WrongTableCount {
+                expected: 3,
+                found: 2
+            }
+        );
+    }
+
+    #[test]
+    fn simplify_01() {
+        let identity = Lut3D::from_fn([5, 3, 4], [0.0, -1.0, 0.5], [1.0, 1.0, 2.0], |rgb| rgb);
+        assert!(identity.is_identity(0.0).unwrap());
+        assert!(matches!(
+            identity.simplify(0.0).unwrap(),
+            Some(SimplifiedLut3D::Identity)
+        ));
+
+        // A matrix with an offset.
+        let m = [[0.5, 0.25, 0.0], [0.0, 1.0, 0.1], [-0.2, 0.0, 2.0]];
+        let matrix = Lut3D::from_fn([5, 3, 4], [0.0, -1.0, 0.5], [1.0, 1.0, 2.0], |(r, g, b)| {
+            (
+                r * 0.5 + g * 0.25 + 0.1,
+                g + b * 0.1,
+                r * -0.2 + b * 2.0 - 0.5,
+            )
+        });
+        assert!(!matrix.is_identity(0.001).unwrap());
+        let (m2, offset) = matrix.to_matrix(0.00001).unwrap().unwrap();
+        for row in 0..3 {
+            for col in 0..3 {
+                assert!((m[row][col] - m2[row][col]).abs() < 0.00001);
+            }
+        }
+        assert!((offset[0] - 0.1).abs() < 0.00001);
+        assert!(offset[1].abs() < 0.00001);
+        assert!((offset[2] + 0.5).abs() < 0.00001);
+        assert!(matrix.to_separable(0.001).unwrap().is_none());
+        assert!(matches!(
+            matrix.simplify(0.00001).unwrap(),
+            Some(SimplifiedLut3D::Matrix(..))
+        ));
+
+        // Per-channel curves.
+        let separable =
+            Lut3D::from_fn([5, 3, 4], [0.0, -1.0, 0.5], [1.0, 1.0, 2.0], |(r, g, b)| {
+                (r * r, g.abs(), b.sqrt())
+            });
+        assert!(separable.to_matrix(0.001).unwrap().is_none());
+        let lut_1d = separable.to_separable(0.0).unwrap().unwrap();
+        assert_eq!(lut_1d.ranges, separable.range.to_vec());
+        assert_eq!(lut_1d.tables[0], vec![0.0, 0.0625, 0.25, 0.5625, 1.0]);
+        assert_eq!(lut_1d.tables[1], vec![1.0, 0.0, 1.0]);
+        assert!(matches!(
+            separable.simplify(0.0).unwrap(),
+            Some(SimplifiedLut3D::Separable(_))
+        ));
+
+        // None of the above, but only barely.
+        let mut lut = separable.clone();
+        lut.tables[1][20] += 0.01;
+        assert!(lut.simplify(0.005).unwrap().is_none());
+        assert!(lut.simplify(0.02).unwrap().is_some());
+
+        // Invalid LUTs are an error, not just unsimplifiable.
+        let mut invalid = identity.clone();
+        invalid.tables[2].pop();
+        let error = LutError::TableLengthMismatch {
+            index: 2,
+            expected: 60,
+            found: 59,
+        };
+        assert_eq!(invalid.is_identity(1.0), Err(error));
+        assert_eq!(invalid.to_matrix(1.0), Err(error));
+        assert_eq!(invalid.to_separable(1.0).unwrap_err(), error);
+        assert!(invalid.simplify(1.0).is_err());
+    }
+
+    #[test]
+    fn smoothed_01() {
+        let identity = Lut3D::from_fn([9; 3], [0.0; 3], [1.0; 3], |rgb| rgb);
+
+        // Linear data is unaffected, including at the edges.
+        assert!(identity
+            .smoothed(1.0)
+            .unwrap()
+            .is_identity(0.00001)
+            .unwrap());
+        assert_eq!(identity.smoothed(0.0).unwrap().tables, identity.tables);
+
+        // Deterministic pseudo-random noise.
+        let mut noisy = identity.clone();
+        let mut state = 12345u32;
+        for table in noisy.tables.iter_mut() {
+            for n in table.iter_mut() {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                *n += ((state >> 8) as f32 / (1 << 24) as f32 - 0.5) * 0.02;
+            }
+        }
+        let smoothed = noisy.smoothed(1.0).unwrap();
+
+        // RMS error of the lattice points off the neutral axis.
+        let rms_err = |lut: &Lut3D| {
+            let mut sum = 0.0f64;
+            for zi in 0..9 {
+                for yi in 0..9 {
+                    for xi in 0..9 {
+                        if !(xi == yi && yi == zi) {
+                            let value = lut.lattice_value([xi, yi, zi]);
+                            let expected = identity.lattice_value([xi, yi, zi]);
+                            for c in 0..3 {
+                                sum += ((value[c] - expected[c]) as f64).powi(2);
+                            }
+                        }
+                    }
+                }
+            }
+            sum.sqrt()
+        };
+        assert!(rms_err(&smoothed) < rms_err(&noisy) * 0.5);
+
+        // Neutral axis (which includes two of the corners) and the other
+        // corners are pinned.
+        for i in 0..9 {
+            assert_eq!(smoothed.lattice_value([i; 3]), noisy.lattice_value([i; 3]));
+        }
+
+        for index in [
+            [8, 0, 0],
+            [0, 8, 0],
+            [0, 0, 8],
+            [8, 8, 0],
+            [8, 0, 8],
+            [0, 8, 8],
+        ] {
+            assert_eq!(smoothed.lattice_value(index), noisy.lattice_value(index));
+        }
+    }
+
+    #[test]
+    fn smoothed_02() {
+        let identity = Lut3D::from_fn([9; 3], [0.0; 3], [1.0; 3], |rgb| rgb);
+
+        // Huge strengths are bounded by the lattice size.
+        for strength in [1.0e9, f32::MAX, f32::INFINITY] {
+            assert!(identity
+                .smoothed(strength)
+                .unwrap()
+                .is_identity(0.00001)
+                .unwrap());
+        }
+
+        let mut invalid = identity.clone();
+        invalid.tables[1].pop();
+        assert!(invalid.smoothed(1.0).is_err());
+    }
 }
@@ -11,6 +11,7 @@
 //! `transform_color()` is provided for that use case.
 
 use crate::chroma::Chromaticities;
+use crate::lut::{Lut1D, Lut3D, LutError};
 
 /// A 3x3 color transform matrix.
 pub type Matrix = [[f64; 3]; 3];
@@ -303,6 +304,229 @@ pub fn to_4x4_f32(m: Matrix) -> [f32; 16] {
     ]
 }
 
+/// Options for `fit()` and `fit_lut_3d()`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FitOptions {
+    /// Also fit an offset that is added after the matrix, making it
+    /// effectively a 3x4 matrix.
+    pub offset: bool,
+    /// Constrain the fit so that white (`[1.0, 1.0, 1.0]`) maps exactly
+    /// to white.
+    pub preserve_white: bool,
+}
+
+/// The result of `fit()` or `fit_lut_3d()`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MatrixFit {
+    pub matrix: Matrix,
+    /// The offset that is added after the matrix.  Zero unless fitted
+    /// with `FitOptions::offset`.
+    pub offset: [f64; 3],
+    /// The largest residual.  The residual of each sample is the
+    /// largest absolute difference of its three channels.
+    pub max_residual: f64,
+    /// The root mean square of the residuals.
+    pub rms_residual: f64,
+}
+
+/// Finds the matrix that best maps the inputs of `pairs` to their
+/// outputs, in the least-squares sense.
+///
+/// `pairs` is a list of `(input, output)` colors.  At least one pair
+/// per unknown in each row is needed: three, or four with
+/// `FitOptions::offset`.  Returns `None` if there are fewer pairs than
+/// that, or they don't vary enough to determine a unique matrix (e.g.
+/// all inputs are gray).
+pub fn fit(pairs: &[([f64; 3], [f64; 3])], options: FitOptions) -> Option<MatrixFit> {
+    let unknowns = if options.offset { 4 } else { 3 };
+    if pairs.len() < unknowns {
+        return None;
+    }
+
+    let mut matrix = [[0.0f64; 3]; 3];
+    let mut offset = [0.0f64; 3];
+    if options.offset {
+        let rows = fit_rows::<4>(pairs, |c| [c[0], c[1], c[2], 1.0], options.preserve_white)?;
+        for (i, row) in rows.iter().enumerate() {
+            matrix[i] = [row[0], row[1], row[2]];
+            offset[i] = row[3];
+        }
+    } else {
+        matrix = fit_rows::<3>(pairs, |c| c, options.preserve_white)?;
+    }
+
+    let mut max_residual = 0.0f64;
+    let mut sum_sq = 0.0f64;
+    for (input, output) in pairs.iter() {
+        let fitted = transform_color(*input, matrix);
+        let residual = (0..3)
+            .map(|i| (fitted[i] + offset[i] - output[i]).abs())
+            .fold(0.0f64, f64::max);
+        max_residual = max_residual.max(residual);
+        sum_sq += residual * residual;
+    }
+
+    Some(MatrixFit {
+        matrix,
+        offset,
+        max_residual,
+        rms_residual: (sum_sq / pairs.len() as f64).sqrt(),
+    })
+}
+
+/// Finds the matrix that best approximates a 3D LUT, in the
+/// least-squares sense.
+///
+/// The LUT's lattice points are used as the samples.  If `pre_curve` is
+/// given, it is applied to the lattice inputs first, so that the fitted
+/// matrix approximates the LUT after the curve, e.g. for LUTs that take
+/// log-encoded input.  It must have either one table or three.
+///
+/// Otherwise the same as `fit()`, except that an invalid LUT or curve
+/// is reported as an error, separately from a failed fit.
+pub fn fit_lut_3d(
+    lut: &Lut3D,
+    pre_curve: Option<&Lut1D>,
+    options: FitOptions,
+) -> Result<Option<MatrixFit>, LutError> {
+    lut.validate()?;
+    if let Some(curve) = pre_curve {
+        curve.validate()?;
+        if curve.tables.len() != 1 && curve.tables.len() != 3 {
+            return Err(LutError::WrongTableCount {
+                expected: 3,
+                found: curve.tables.len(),
+            });
+        }
+    }
+
+    let inc = [0, 1, 2].map(|axis| {
+        (lut.range[axis].1 as f64 - lut.range[axis].0 as f64) / (lut.resolution[axis] - 1) as f64
+    });
+    let input = |axis: usize, i: usize| {
+        let n = lut.range[axis].0 + (inc[axis] * i as f64) as f32;
+        match pre_curve {
+            Some(curve) => curve.look_up(n, axis.min(curve.tables.len() - 1)) as f64,
+            None => n as f64,
+        }
+    };
+
+    let mut pairs = Vec::with_capacity(lut.tables[0].len());
+    for zi in 0..lut.resolution[2] {
+        for yi in 0..lut.resolution[1] {
+            for xi in 0..lut.resolution[0] {
+                let i =
+                    xi + (yi * lut.resolution[0]) + (zi * lut.resolution[0] * lut.resolution[1]);
+                pairs.push((
+                    [input(0, xi), input(1, yi), input(2, zi)],
+                    [
+                        lut.tables[0][i] as f64,
+                        lut.tables[1][i] as f64,
+                        lut.tables[2][i] as f64,
+                    ],
+                ));
+            }
+        }
+    }
+
+    Ok(fit(&pairs, options))
+}
+
+/// Fits each row of an `N`-column matrix, where `terms` expands an input
+/// color into the `N` terms that the row is multiplied with.
+///
+/// With `preserve_white`, each row is constrained to sum to 1, which
+/// maps white to white as long as the terms of white are all 1.
+fn fit_rows<const N: usize>(
+    pairs: &[([f64; 3], [f64; 3])],
+    terms: impl Fn([f64; 3]) -> [f64; N],
+    preserve_white: bool,
+) -> Option<[[f64; N]; 3]> {
+    // Normal equations, shared by all rows.
+    let mut normal = [[0.0f64; N]; N];
+    let mut rhs = [[0.0f64; N]; 3];
+    for (input, output) in pairs.iter() {
+        let t = terms(*input);
+        for i in 0..N {
+            for j in 0..N {
+                normal[i][j] += t[i] * t[j];
+            }
+            for row in 0..3 {
+                rhs[row][i] += t[i] * output[row];
+            }
+        }
+    }
+    let normal_inv = inverse_n(normal)?;
+    let solve = |b: [f64; N]| {
+        let mut x = [0.0f64; N];
+        for i in 0..N {
+            x[i] = (0..N).map(|j| normal_inv[i][j] * b[j]).sum();
+        }
+        x
+    };
+
+    let mut rows = [[0.0f64; N]; 3];
+    for (row, b) in rows.iter_mut().zip(rhs.iter()) {
+        *row = solve(*b);
+        if preserve_white {
+            // Project onto the constraint `sum(row) == 1`, using the
+            // Lagrange multiplier solution for equality-constrained
+            // least squares.
+            let g = solve([1.0; N]);
+            let g_sum: f64 = g.iter().sum();
+            let k = (1.0 - row.iter().sum::<f64>()) / g_sum;
+            for (x, gi) in row.iter_mut().zip(g.iter()) {
+                *x += k * gi;
+            }
+        }
+    }
+
+    Some(rows)
+}
+
+/// Inverts an `N`x`N` matrix via Gauss-Jordan elimination.
+///
+/// Returns `None` if the matrix is singular or nearly so.
+fn inverse_n<const N: usize>(m: [[f64; N]; N]) -> Option<[[f64; N]; N]> {
+    let scale = m.iter().flatten().fold(0.0f64, |a, b| a.max(b.abs()));
+    if scale.is_nan() || scale == 0.0 {
+        return None;
+    }
+
+    let mut a = m;
+    let mut inv = [[0.0f64; N]; N];
+    for (i, row) in inv.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+
+    for col in 0..N {
+        // Partial pivoting.
+        let pivot = (col..N).max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))?;
+        if a[pivot][col].abs() <= scale * 1.0e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+
+        let p = 1.0 / a[col][col];
+        for j in 0..N {
+            a[col][j] *= p;
+            inv[col][j] *= p;
+        }
+        for row in 0..N {
+            if row != col {
+                let f = a[row][col];
+                for j in 0..N {
+                    a[row][j] -= f * a[col][j];
+                    inv[row][j] -= f * inv[col][j];
+                }
+            }
+        }
+    }
+
+    Some(inv)
+}
+
 //-------------------------------------------------------------
 
 #[cfg(test)]
@@ -561,4 +785,108 @@ mod tests {
             assert_eq!(n1, n2);
         }
     }
+
+    #[test]
+    fn fit_01() {
+        let mat = rgb_to_rgb_matrix(crate::chroma::REC709, crate::chroma::ACES_AP0);
+        let mut pairs = Vec::new();
+        for i in 0..27 {
+            let input = [
+                (i % 3) as f64 * 0.5,
+                ((i / 3) % 3) as f64 * 0.5,
+                (i / 9) as f64 * 0.5,
+            ];
+            let output = transform_color(input, mat);
+            pairs.push((input, output.map(|n| n + 0.25)));
+        }
+
+        // Exact with an offset.
+        let options = FitOptions {
+            offset: true,
+            preserve_white: false,
+        };
+        let result = fit(&pairs, options).unwrap();
+        assert!(matrix_max_diff(result.matrix, mat) < 0.000_000_001);
+        assert!(vec_max_diff(result.offset, [0.25; 3]) < 0.000_000_001);
+        assert!(result.max_residual < 0.000_000_001);
+
+        // Not exact without one.
+        let result = fit(&pairs, FitOptions::default()).unwrap();
+        assert_eq!(result.offset, [0.0; 3]);
+        assert!(result.max_residual > 0.01);
+        assert!(result.rms_residual <= result.max_residual);
+
+        // White preserving.
+        let options = FitOptions {
+            offset: true,
+            preserve_white: true,
+        };
+        let result = fit(&pairs, options).unwrap();
+        let white = transform_color([1.0; 3], result.matrix);
+        let white = [0, 1, 2].map(|i| white[i] + result.offset[i]);
+        assert!(vec_max_diff(white, [1.0; 3]) < 0.000_000_001);
+
+        // Degenerate.
+        let gray: Vec<_> = (0..8).map(|i| ([i as f64; 3], [i as f64; 3])).collect();
+        assert!(fit(&gray, FitOptions::default()).is_none());
+        assert!(fit(&[], FitOptions::default()).is_none());
+        let options = FitOptions {
+            offset: true,
+            preserve_white: false,
+        };
+        let basis = [pairs[1], pairs[3], pairs[9]];
+        assert!(fit(&basis, options).is_none());
+        assert!(fit(&basis, FitOptions::default()).is_some());
+    }
+
+    #[test]
+    fn fit_lut_3d_01() {
+        let mat = rgb_to_rgb_matrix(crate::chroma::REC709, crate::chroma::ACES_AP1);
+        let lut = Lut3D::from_fn([9; 3], [0.0; 3], [1.0; 3], |(r, g, b)| {
+            let c = transform_color([(r * r) as f64, (g * g) as f64, (b * b) as f64], mat);
+            (c[0] as f32, c[1] as f32, c[2] as f32)
+        });
+
+        let result = fit_lut_3d(&lut, None, FitOptions::default())
+            .unwrap()
+            .unwrap();
+        assert!(result.max_residual > 0.01);
+
+        let curve = Lut1D::from_fn_1(4097, 0.0, 1.0, |n| n * n);
+        let result = fit_lut_3d(&lut, Some(&curve), FitOptions::default())
+            .unwrap()
+            .unwrap();
+        assert!(matrix_max_diff(result.matrix, mat) < 0.000_1);
+        assert!(result.max_residual < 0.000_1);
+
+        let mut invalid = lut.clone();
+        invalid.tables[0].pop();
+        assert_eq!(
+            fit_lut_3d(&invalid, None, FitOptions::default()),
+            Err(LutError::TableLengthMismatch {
+                index: 0,
+                expected: 729,
+                found: 728
+            })
+        );
+        let bad_curve = Lut1D {
+            ranges: vec![(0.0, 1.0)],
+            tables: vec![vec![0.0, 1.0]; 2],
+        };
+        assert_eq!(
+            fit_lut_3d(&lut, Some(&bad_curve), FitOptions::default()),
+            Err(LutError::WrongTableCount {
+                expected: 3,
+                found: 2
+            })
+        );
+
+        // A valid LUT that can't be fitted.
+        let gray = Lut3D::from_fn([2; 3], [0.0; 3], [1.0; 3], |_| (0.5, 0.5, 0.5));
+        let flat_curve = Lut1D::from_fn_1(2, 0.0, 1.0, |_| 0.5);
+        assert_eq!(
+            fit_lut_3d(&gray, Some(&flat_curve), FitOptions::default()),
+            Ok(None)
+        );
+    }
 }